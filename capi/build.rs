@@ -0,0 +1,23 @@
+use std::path::Path;
+
+/// Generates the C header into `OUT_DIR`. The copy in `include/` is checked
+/// in, and `tests/c_api.rs` fails if it differs from this one.
+fn main() {
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    let dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    let mut config = cbindgen::Config::default();
+    config.usize_is_size_t = true;
+    cbindgen::Builder::new()
+        .with_config(config)
+        .with_crate(&dir)
+        .with_language(cbindgen::Language::C)
+        .with_include_guard("DEMO_H")
+        .with_header(
+            "/* Generated from capi/src/lib.rs by its build script. To update it, run\n \
+             * DEMO_UPDATE_HEADER=1 cargo test -p demo-capi --test c_api */",
+        )
+        .generate()
+        .expect("generating the C header")
+        .write_to_file(Path::new(&out_dir).join("demo.h"));
+}
@@ -0,0 +1,73 @@
+/* Generated from capi/src/lib.rs by its build script. To update it, run
+ * DEMO_UPDATE_HEADER=1 cargo test -p demo-capi --test c_api */
+
+#ifndef DEMO_H
+#define DEMO_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * A loaded demo. Only one thread may use it at a time.
+ */
+typedef struct DemoRunner DemoRunner;
+
+/**
+ * Loads the wasm module at `path`, a UTF-8 string. Returns NULL if that
+ * fails.
+ *
+ * # Safety
+ *
+ * `path` must be NULL or a NUL-terminated string.
+ */
+struct DemoRunner *demo_runner_new_from_file(const char *path);
+
+/**
+ * Renders the frame at `time` seconds, `width` by `height` pixels big, into
+ * `buffer`. Pixels are RGBA, a byte per channel, in rows from the top
+ * without padding, so `buffer_len` must be at least `width * height * 4`.
+ * Returns 0, or -1 if rendering fails.
+ *
+ * # Safety
+ *
+ * `runner` must come from `demo_runner_new_from_file` and `buffer` must be
+ * writable for `buffer_len` bytes.
+ */
+int demo_runner_render(struct DemoRunner *runner,
+                       double time,
+                       int32_t width,
+                       int32_t height,
+                       uint8_t *buffer,
+                       size_t buffer_len);
+
+/**
+ * Sets the demo parameter called `name`, a UTF-8 string, to `value`.
+ * Returns 0, or -1 if the demo has no such parameter.
+ *
+ * # Safety
+ *
+ * `runner` must come from `demo_runner_new_from_file` and `name` must be
+ * NULL or a NUL-terminated string.
+ */
+int demo_runner_set_param(struct DemoRunner *runner, const char *name, double value);
+
+/**
+ * Unloads the demo. Does nothing if `runner` is NULL.
+ *
+ * # Safety
+ *
+ * `runner` must come from `demo_runner_new_from_file` and not be used
+ * afterwards.
+ */
+void demo_runner_free(struct DemoRunner *runner);
+
+/**
+ * Why the last call on this thread failed, or NULL if it succeeded. The
+ * string stays valid until the next call.
+ */
+const char *demo_last_error(void);
+
+#endif /* DEMO_H */
@@ -0,0 +1,150 @@
+//! C bindings for embedding demos in programs written in other languages.
+//! `include/demo.h` declares them; it is generated by the build script and
+//! checked in.
+//!
+//! Functions that can fail return NULL or -1 and leave a message for
+//! `demo_last_error`. Errors and panics never cross into the caller.
+
+use anyhow::Context;
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+
+/// A loaded demo. Only one thread may use it at a time.
+pub struct DemoRunner(demo::DemoRunner);
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Runs `f` and records how it failed, if it did, for `demo_last_error`.
+fn call<T>(f: impl FnOnce() -> anyhow::Result<T>) -> Option<T> {
+    let result = catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|panic| {
+        let message = panic
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        Err(anyhow::anyhow!("panicked: {message}"))
+    });
+    let (value, error) = match result {
+        Ok(value) => (Some(value), None),
+        Err(error) => (None, Some(format!("{error:#}").replace('\0', " "))),
+    };
+    LAST_ERROR.with(|last| *last.borrow_mut() = error.and_then(|e| CString::new(e).ok()));
+    value
+}
+
+unsafe fn str_arg<'a>(arg: *const c_char, what: &str) -> anyhow::Result<&'a str> {
+    anyhow::ensure!(!arg.is_null(), "{what} is NULL");
+    Ok(CStr::from_ptr(arg).to_str()?)
+}
+
+unsafe fn runner_arg<'a>(runner: *mut DemoRunner) -> anyhow::Result<&'a mut demo::DemoRunner> {
+    anyhow::ensure!(!runner.is_null(), "the runner is NULL");
+    Ok(&mut (*runner).0)
+}
+
+/// Loads the wasm module at `path`, a UTF-8 string. Returns NULL if that
+/// fails.
+///
+/// # Safety
+///
+/// `path` must be NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn demo_runner_new_from_file(path: *const c_char) -> *mut DemoRunner {
+    call(|| {
+        let path = str_arg(path, "the path")?;
+        Ok(Box::into_raw(Box::new(DemoRunner(demo::create_file(
+            path,
+        )?))))
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Renders the frame at `time` seconds, `width` by `height` pixels big, into
+/// `buffer`. Pixels are RGBA, a byte per channel, in rows from the top
+/// without padding, so `buffer_len` must be at least `width * height * 4`.
+/// Returns 0, or -1 if rendering fails.
+///
+/// # Safety
+///
+/// `runner` must come from `demo_runner_new_from_file` and `buffer` must be
+/// writable for `buffer_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn demo_runner_render(
+    runner: *mut DemoRunner,
+    time: f64,
+    width: i32,
+    height: i32,
+    buffer: *mut u8,
+    buffer_len: usize,
+) -> c_int {
+    let rendered = call(|| {
+        let runner = runner_arg(runner)?;
+        anyhow::ensure!(width > 0 && height > 0, "{width}x{height} is empty");
+        let row = width as usize * 4;
+        let len = row
+            .checked_mul(height as usize)
+            .filter(|len| *len <= buffer_len)
+            .with_context(|| format!("a {width}x{height} frame doesn't fit {buffer_len} bytes"))?;
+        anyhow::ensure!(!buffer.is_null(), "the buffer is NULL");
+        let buffer = std::slice::from_raw_parts_mut(buffer, len);
+        let frame = runner.render_frame(time, &demo::Rect { width, height })?;
+        for (y, out) in buffer.chunks_exact_mut(row).enumerate() {
+            out.copy_from_slice(&frame.data[y * frame.stride..][..row]);
+        }
+        Ok(())
+    });
+    if rendered.is_some() {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Sets the demo parameter called `name`, a UTF-8 string, to `value`.
+/// Returns 0, or -1 if the demo has no such parameter.
+///
+/// # Safety
+///
+/// `runner` must come from `demo_runner_new_from_file` and `name` must be
+/// NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn demo_runner_set_param(
+    runner: *mut DemoRunner,
+    name: *const c_char,
+    value: f64,
+) -> c_int {
+    let set = call(|| runner_arg(runner)?.set_param(str_arg(name, "the name")?, value));
+    if set.is_some() {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Unloads the demo. Does nothing if `runner` is NULL.
+///
+/// # Safety
+///
+/// `runner` must come from `demo_runner_new_from_file` and not be used
+/// afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn demo_runner_free(runner: *mut DemoRunner) {
+    if !runner.is_null() {
+        drop(Box::from_raw(runner));
+    }
+}
+
+/// Why the last call on this thread failed, or NULL if it succeeded. The
+/// string stays valid until the next call.
+#[no_mangle]
+pub extern "C" fn demo_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |error| error.as_ptr())
+    })
+}
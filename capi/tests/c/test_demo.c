@@ -0,0 +1,54 @@
+/* Exercises the C API against the guest module passed as the argument. */
+
+#include "demo.h"
+
+#include <stdio.h>
+#include <string.h>
+
+#define CHECK(condition)                                                     \
+    do {                                                                     \
+        if (!(condition)) {                                                  \
+            const char *error = demo_last_error();                           \
+            fprintf(stderr, "%s:%d: %s (last error: %s)\n", __FILE__,        \
+                    __LINE__, #condition, error ? error : "none");           \
+            return 1;                                                        \
+        }                                                                    \
+    } while (0)
+
+static int all_equal(const uint8_t *data, size_t len, uint8_t value) {
+    for (size_t i = 0; i < len; i++) {
+        if (data[i] != value) {
+            return 0;
+        }
+    }
+    return 1;
+}
+
+int main(int argc, char **argv) {
+    CHECK(argc == 2);
+
+    CHECK(demo_runner_new_from_file("does-not-exist.wasm") == NULL);
+    CHECK(demo_last_error() != NULL);
+
+    DemoRunner *runner = demo_runner_new_from_file(argv[1]);
+    CHECK(runner != NULL);
+    CHECK(demo_last_error() == NULL);
+
+    uint8_t frame[4 * 3 * 4];
+    memset(frame, 0xff, sizeof frame);
+    CHECK(demo_runner_render(runner, 0.0, 4, 3, frame, sizeof frame) == 0);
+    CHECK(all_equal(frame, sizeof frame, 0));
+
+    CHECK(demo_runner_set_param(runner, "level", 7.0) == 0);
+    CHECK(demo_runner_render(runner, 0.0, 4, 3, frame, sizeof frame) == 0);
+    CHECK(all_equal(frame, sizeof frame, 7));
+
+    CHECK(demo_runner_set_param(runner, "missing", 1.0) == -1);
+    CHECK(strstr(demo_last_error(), "no parameter \"missing\"") != NULL);
+    CHECK(demo_runner_render(runner, 0.0, 8, 8, frame, sizeof frame) == -1);
+    CHECK(strstr(demo_last_error(), "doesn't fit") != NULL);
+
+    demo_runner_free(runner);
+    demo_runner_free(NULL);
+    return 0;
+}
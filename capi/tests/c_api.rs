@@ -0,0 +1,73 @@
+//! Checks the checked-in C header, and builds `tests/c/test_demo.c` against
+//! the shared library and runs it.
+
+use std::path::Path;
+use std::process::Command;
+
+/// Fills frames with its `level` parameter.
+const GUEST: &str = r#"(module
+    (memory (export "memory") 1)
+    (global $level (mut f64) (f64.const 0))
+    (data (i32.const 16) "level\00")
+    (func (export "param_name") (param i32) (result i32)
+        (select (i32.const 16) (i32.const 0) (i32.eqz (local.get 0))))
+    (func (export "get_param") (param i32) (result f64) global.get $level)
+    (func (export "set_param") (param i32 f64) local.get 1 global.set $level)
+    (func (export "render") (param f64 i32 i32) (result i32)
+        (memory.fill
+            (i32.const 1024)
+            (i32.trunc_f64_u (global.get $level))
+            (i32.mul (i32.mul (local.get 1) (local.get 2)) (i32.const 4)))
+        i32.const 1024))"#;
+
+/// The header the build script generated from the current source.
+const HEADER: &str = include_str!(concat!(env!("OUT_DIR"), "/demo.h"));
+
+#[test]
+fn test_header() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("include/demo.h");
+    if std::env::var_os("DEMO_UPDATE_HEADER").is_some() {
+        std::fs::write(&path, HEADER).unwrap();
+    }
+    let checked_in = std::fs::read_to_string(&path).unwrap();
+    assert!(
+        checked_in == HEADER,
+        "{} is out of date; run DEMO_UPDATE_HEADER=1 cargo test -p demo-capi --test c_api",
+        path.display()
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_c_api() {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let tmp = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    // Integration tests run from `target/<profile>/deps`, next to which
+    // Cargo puts the library.
+    let exe = std::env::current_exe().unwrap();
+    let lib_dir = exe.parent().unwrap().parent().unwrap();
+    assert!(lib_dir.join("libdemo_capi.so").is_file());
+
+    let program = tmp.join("test_demo");
+    let status = Command::new("cc")
+        .arg(manifest_dir.join("tests/c/test_demo.c"))
+        .arg("-std=c99")
+        .arg("-Wall")
+        .arg("-Werror")
+        .arg("-I")
+        .arg(manifest_dir.join("include"))
+        .arg("-L")
+        .arg(lib_dir)
+        .arg(format!("-Wl,-rpath,{}", lib_dir.display()))
+        .arg("-ldemo_capi")
+        .arg("-o")
+        .arg(&program)
+        .status()
+        .expect("running cc");
+    assert!(status.success(), "cc failed");
+
+    let guest = tmp.join("test_demo.wat");
+    std::fs::write(&guest, GUEST).unwrap();
+    let status = Command::new(&program).arg(&guest).status().unwrap();
+    assert!(status.success(), "{} failed", program.display());
+}
@@ -9,6 +9,42 @@ extern "C" {
     fn output(str: *const std::ffi::c_char);
 }
 
+#[cfg(all(feature = "spans", not(test)))]
+extern "C" {
+    fn span_begin(name: *const std::ffi::c_char);
+    fn span_end();
+}
+
+/// Times the rest of the enclosing block as a host profiling span when built
+/// with the `spans` feature, and does nothing otherwise.
+macro_rules! span {
+    ($name:literal) => {
+        let _span = Span::begin(concat!($name, "\0"));
+    };
+}
+
+struct Span;
+
+impl Span {
+    #[allow(unused_variables)]
+    fn begin(name: &'static str) -> Span {
+        #[cfg(all(feature = "spans", not(test)))]
+        unsafe {
+            span_begin(name.as_ptr() as *const std::ffi::c_char);
+        }
+        Span
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        #[cfg(all(feature = "spans", not(test)))]
+        unsafe {
+            span_end();
+        }
+    }
+}
+
 mod linear;
 use linear::*;
 
@@ -24,25 +60,105 @@ macro_rules! outputln {
             }};
     }
 
+/// Routes panics through the host's logging import, which adds the guest
+/// backtrace, instead of trapping with `unreachable` and no message.
+fn init() {
+    static INIT: std::sync::Once = std::sync::Once::new();
+    INIT.call_once(|| {
+        std::panic::set_hook(Box::new(|info| outputln!("{info}")));
+    });
+}
+
+/// Read by the host's module catalogue, one `key = value` per line.
+#[used]
+#[link_section = "demo"]
+static METADATA: [u8; 34] = *b"abi = 1\ntitle = Cube minus sphere\n";
+
 const DIMENSIONS :[[i32;2];3] = [[320, 240],[640,480],[0,0]];
 
 #[allow(unused_variables)]
 #[no_mangle]
 fn get_dimensions(dpi: i32) -> i32{
+    init();
     unsafe {
         DIMENSIONS.as_ptr() as i32
     }
 }
 
-const STATIC_WIDTH: usize = 1024;
-const STATIC_HEIGHT: usize = 768;
-static mut PIX_BUF: [[u8; 4]; STATIC_WIDTH * STATIC_HEIGHT] =
-    [[0; 4]; STATIC_WIDTH * STATIC_HEIGHT];
+/// Raymarching every physical pixel of a high-DPI display is too slow, so
+/// ask the host for logical resolution and let it stretch the frame.
+#[allow(unused_variables)]
+#[no_mangle]
+pub fn set_dpi(dpi: i32) -> i32 {
+    init();
+    1
+}
+
+// Two buffers so the host can keep reading the last frame in place while the
+// next one is being drawn.
+static mut FRAMEBUFFERS: [Vec<[u8; 4]>; 2] = [Vec::new(), Vec::new()];
+static mut BACK_BUFFER: usize = 0;
 
-pub fn set_px(x: usize, y: usize, width: usize, r: u8, g: u8, b: u8, a: u8) {
-    unsafe {
-        PIX_BUF[y * width + x] = [r, g, b, a];
+fn framebuffers() -> &'static mut [Vec<[u8; 4]>; 2] {
+    unsafe { &mut *std::ptr::addr_of_mut!(FRAMEBUFFERS) }
+}
+
+fn framebuffer() -> &'static mut Vec<[u8; 4]> {
+    unsafe { &mut framebuffers()[BACK_BUFFER] }
+}
+
+/// Called by the host whenever the output dimensions change. Returns the
+/// address of the `width * height` RGBA buffer the next frame is drawn into.
+#[no_mangle]
+pub fn resize_framebuffer(width: i32, height: i32) -> i32 {
+    init();
+    let len = width.max(0) as usize * height.max(0) as usize;
+    for buf in framebuffers().iter_mut() {
+        if buf.len() != len {
+            buf.resize(len, [0; 4]);
+        }
     }
+    framebuffer().as_ptr() as i32
+}
+
+// Tunable from the host while the demo runs.
+const PARAM_NAMES: [&str; 2] = ["field_of_view\0", "hue_speed\0"];
+const FIELD_OF_VIEW: usize = 0;
+const HUE_SPEED: usize = 1;
+static mut PARAMS: [f64; 2] = [45.0, 10.0];
+
+fn params() -> &'static mut [f64; 2] {
+    unsafe { &mut *std::ptr::addr_of_mut!(PARAMS) }
+}
+
+fn param(index: usize) -> f32 {
+    params()[index] as f32
+}
+
+#[no_mangle]
+pub fn param_name(index: i32) -> i32 {
+    init();
+    PARAM_NAMES
+        .get(index as usize)
+        .map_or(0, |name| name.as_ptr() as i32)
+}
+
+#[no_mangle]
+pub fn get_param(index: i32) -> f64 {
+    init();
+    params().get(index as usize).copied().unwrap_or_default()
+}
+
+#[no_mangle]
+pub fn set_param(index: i32, value: f64) {
+    init();
+    if let Some(param) = params().get_mut(index as usize) {
+        *param = value;
+    }
+}
+
+pub fn set_px(x: usize, y: usize, width: usize, r: u8, g: u8, b: u8, a: u8) {
+    framebuffer()[y * width + x] = [r, g, b, a];
 }
 
 const EPSILON: f32 = 0.0001;
@@ -119,6 +235,10 @@ fn hsv_to_rgb(h: u8, s: u8, v: u8) -> (u8, u8, u8) {
 #[allow(unused_variables)]
 #[no_mangle]
 pub fn render(time: f64, width: i32, height: i32) -> i32 {
+    init();
+    span!("render");
+    // Hosts that predate `resize_framebuffer` never call it.
+    let ptr = resize_framebuffer(width, height);
     let t = time / 5000.0;
     let rotation = Mat4::rotation(t as f32, t as f32 / 2.0, t as f32 / 3.0);
     // let rotation = Mat4::identity();
@@ -128,23 +248,27 @@ pub fn render(time: f64, width: i32, height: i32) -> i32 {
         sdf_cube_minus_sphere(mv)
     };
     for i in 0..width as usize {
+        // Spans cost a host call each, so none go below a column of pixels.
+        span!("column");
         for j in 0..height as usize {
-            let dir = ray_direction(45.0, width as f32, height as f32, i as f32, j as f32);
+            let dir = ray_direction(param(FIELD_OF_VIEW), width as f32, height as f32, i as f32, j as f32);
             let dist = shortest_distance_to_surface(df, eye, dir, MIN_DIST, MAX_DIST);
-            // return unsafe { PIX_BUF.as_ptr() as i32 };
             if dist > MAX_DIST - EPSILON {
                 set_px(i, j, width as usize, 0, 0, 0, 255);
             } else {
                 let p = eye + (dir * dist);
                 let normal = gradient(df, p);
                 let intensity = ((-dir.dot(normal)).max(0.0) * 255.0) as u8;
-                let (r, g, b) = hsv_to_rgb((t*10.0 % 255.0) as u8, intensity, 100  );
+                let (r, g, b) = hsv_to_rgb((t as f32 * param(HUE_SPEED) % 255.0) as u8, intensity, 100  );
                 set_px(i, j, width as usize, r,g,b,255);
             }
         }
     }
 
-    unsafe { PIX_BUF.as_ptr() as i32 }
+    unsafe {
+        BACK_BUFFER ^= 1;
+    }
+    ptr
 }
 
 const CUBE_SIZE: f32 = 0.5;
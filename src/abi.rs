@@ -0,0 +1,298 @@
+use crate::plugin::Capability;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use wasmparser::types::{EntityType, Types};
+use wasmparser::ValType::{F64, I32};
+use wasmparser::{Parser, Payload, ValType, Validator};
+
+/// The newest guest ABI this host runs. Guests declare the version they were
+/// written for with an `abi` key in their metadata, and default to 1.
+pub const ABI_VERSION: u32 = 1;
+
+type Signature = (&'static str, &'static [ValType], &'static [ValType]);
+
+/// The functions a guest may export, as `DemoRunner` calls them. Only
+/// `render` is required.
+const EXPORTS: &[Signature] = &[
+    ("render", &[F64, I32, I32], &[I32]),
+    ("set_dimensions", &[I32; 7], &[I32, I32]),
+    ("resize_framebuffer", &[I32, I32], &[I32]),
+    ("damage", &[], &[I32]),
+    ("set_dpi", &[I32], &[I32]),
+    ("param_name", &[I32], &[I32]),
+    ("get_param", &[I32], &[F64]),
+    ("set_param", &[I32, F64], &[]),
+    ("pointer_move", &[I32, I32], &[]),
+    ("pointer_button", &[I32, I32], &[]),
+    ("key", &[I32, I32], &[]),
+];
+
+/// The functions the host links into the `env` module, and the capability
+/// a guest's policy must allow for it to import each.
+const IMPORTS: &[(Signature, Capability)] = &[
+    (("output", &[I32], &[]), Capability::Logging),
+    (("span_begin", &[I32], &[]), Capability::Logging),
+    (("span_end", &[], &[]), Capability::Logging),
+];
+
+/// The capability needed to import `module.name`, or `None` if the host
+/// doesn't provide it.
+pub fn import_capability(module: &str, name: &str) -> Option<Capability> {
+    IMPORTS
+        .iter()
+        .find(|((n, ..), _)| module == "env" && *n == name)
+        .map(|(_, capability)| *capability)
+}
+
+/// An import or export and its type, e.g. `func(f64, i32, i32) -> i32`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Item {
+    pub name: String,
+    pub ty: String,
+}
+
+/// What a module offers and needs, read without compiling it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct Inspection {
+    pub exports: Vec<Item>,
+    /// Named `module.name`.
+    pub imports: Vec<Item>,
+    pub abi_version: u32,
+    /// `key = value` lines from the module's `demo` custom section, e.g.
+    /// `title` and `author`.
+    pub metadata: BTreeMap<String, String>,
+    /// Why the host can't run the module; empty if it can.
+    pub problems: Vec<String>,
+}
+
+/// Validates `bytes` as a wasm module and checks it against the guest ABI.
+/// Like `DemoRunner`, this accepts the text format too. Only modules that
+/// aren't valid wasm at all are an error.
+///
+/// ```
+/// let inspection = demo::abi::inspect(br#"(module (memory (export "memory") 1))"#)?;
+/// assert_eq!(inspection.problems, ["doesn't export `render`"]);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub fn inspect(bytes: &[u8]) -> anyhow::Result<Inspection> {
+    let bytes = &*wat::parse_bytes(bytes)?;
+    let types = Validator::new().validate_all(bytes)?;
+    let mut inspection = Inspection {
+        abi_version: 1,
+        ..Default::default()
+    };
+    let mut has_memory = false;
+    for payload in Parser::new(0).parse_all(bytes) {
+        match payload? {
+            Payload::ImportSection(imports) => {
+                for import in imports {
+                    let import = import?;
+                    let name = format!("{}.{}", import.module, import.name);
+                    let ty = types
+                        .entity_type_from_import(&import)
+                        .map(|ty| describe(&types, &ty))
+                        .unwrap_or_default();
+                    let provided = IMPORTS
+                        .iter()
+                        .find(|((n, ..), _)| import.module == "env" && *n == import.name)
+                        .map(|(signature, _)| signature);
+                    match provided {
+                        None => inspection
+                            .problems
+                            .push(format!("imports {name}, which the host doesn't provide")),
+                        Some(signature) if ty != describe_signature(signature) => {
+                            inspection.problems.push(format!(
+                                "imports {name} as {ty}, but the host provides {}",
+                                describe_signature(signature)
+                            ))
+                        }
+                        Some(_) => {}
+                    }
+                    inspection.imports.push(Item { name, ty });
+                }
+            }
+            Payload::ExportSection(exports) => {
+                for export in exports {
+                    let export = export?;
+                    let ty = types
+                        .entity_type_from_export(&export)
+                        .map(|ty| describe(&types, &ty))
+                        .unwrap_or_default();
+                    if export.name == "memory" {
+                        has_memory = ty == "memory";
+                    }
+                    if let Some(signature) = EXPORTS.iter().find(|(n, ..)| *n == export.name) {
+                        let expected = describe_signature(signature);
+                        if ty != expected {
+                            inspection.problems.push(format!(
+                                "exports {} as {ty}, but the host calls it as {expected}",
+                                export.name
+                            ));
+                        }
+                    }
+                    inspection.exports.push(Item {
+                        name: export.name.to_string(),
+                        ty,
+                    });
+                }
+            }
+            Payload::CustomSection(section) if section.name() == "demo" => {
+                parse_metadata(
+                    section.data(),
+                    &mut inspection.metadata,
+                    &mut inspection.problems,
+                );
+            }
+            _ => {}
+        }
+    }
+
+    if !has_memory {
+        inspection
+            .problems
+            .push("doesn't export its memory as `memory`".to_string());
+    }
+    if !inspection.exports.iter().any(|e| e.name == "render") {
+        inspection
+            .problems
+            .push("doesn't export `render`".to_string());
+    }
+    if let Some(abi) = inspection.metadata.get("abi") {
+        match abi.parse() {
+            Ok(version) => inspection.abi_version = version,
+            Err(_) => inspection
+                .problems
+                .push(format!("ABI version {abi:?} is not a number")),
+        }
+    }
+    if inspection.abi_version > ABI_VERSION {
+        inspection.problems.push(format!(
+            "needs ABI version {}, but the host only supports up to {ABI_VERSION}",
+            inspection.abi_version
+        ));
+    }
+    Ok(inspection)
+}
+
+/// The `key = value` lines of a module's `demo` custom section, skipping
+/// any that are malformed. Unlike `inspect`, this doesn't validate the
+/// module.
+pub fn metadata(bytes: &[u8]) -> anyhow::Result<BTreeMap<String, String>> {
+    let bytes = &*wat::parse_bytes(bytes)?;
+    let mut metadata = BTreeMap::new();
+    for payload in Parser::new(0).parse_all(bytes) {
+        if let Payload::CustomSection(section) = payload? {
+            if section.name() == "demo" {
+                parse_metadata(section.data(), &mut metadata, &mut Vec::new());
+            }
+        }
+    }
+    Ok(metadata)
+}
+
+fn parse_metadata(
+    data: &[u8],
+    metadata: &mut BTreeMap<String, String>,
+    problems: &mut Vec<String>,
+) {
+    let text = String::from_utf8_lossy(data);
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_once('=') {
+            Some((key, value)) => {
+                metadata.insert(key.trim().to_string(), value.trim().to_string());
+            }
+            None => problems.push(format!("metadata line {line:?} is not `key = value`")),
+        }
+    }
+}
+
+fn describe(types: &Types, ty: &EntityType) -> String {
+    match ty {
+        EntityType::Func(id) => {
+            let func = types[*id].unwrap_func();
+            describe_func(func.params(), func.results())
+        }
+        EntityType::Table(_) => "table".to_string(),
+        EntityType::Memory(_) => "memory".to_string(),
+        EntityType::Global(_) => "global".to_string(),
+        EntityType::Tag(_) => "tag".to_string(),
+    }
+}
+
+fn describe_signature((_, params, results): &Signature) -> String {
+    describe_func(params, results)
+}
+
+fn describe_func(params: &[ValType], results: &[ValType]) -> String {
+    let list = |types: &[ValType]| {
+        types
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    match results {
+        [] => format!("func({})", list(params)),
+        [result] => format!("func({}) -> {result}", list(params)),
+        _ => format!("func({}) -> ({})", list(params), list(results)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn inspect_wat(wat: &str) -> Inspection {
+        inspect(&wat::parse_str(wat).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_inspect() {
+        let ok = inspect_wat(
+            r#"(module
+                (import "env" "output" (func (param i32)))
+                (memory (export "memory") 1)
+                (func (export "render") (param f64 i32 i32) (result i32) i32.const 0)
+                (@custom "demo" "title = Cube\nabi = 1\n"))"#,
+        );
+        assert_eq!(ok.problems, Vec::<String>::new());
+        assert_eq!(ok.metadata["title"], "Cube");
+        assert_eq!(ok.imports[0].ty, "func(i32)");
+        assert_eq!(ok.exports[1].ty, "func(f64, i32, i32) -> i32");
+        let metadata = metadata(br#"(module (@custom "demo" "title = Cube\nbad"))"#).unwrap();
+        assert_eq!(
+            metadata.into_iter().collect::<Vec<_>>(),
+            [("title".into(), "Cube".into())]
+        );
+
+        assert_eq!(
+            import_capability("env", "output"),
+            Some(Capability::Logging)
+        );
+        assert_eq!(import_capability("env", "clock"), None);
+        assert_eq!(import_capability("wasi", "output"), None);
+
+        let incompatible = inspect_wat(
+            r#"(module
+                (import "env" "clock" (func (result f64)))
+                (func (export "render") (param f64 i32 i32))
+                (@custom "demo" "abi = 99"))"#,
+        );
+        assert_eq!(
+            incompatible.problems,
+            [
+                "imports env.clock, which the host doesn't provide",
+                "exports render as func(f64, i32, i32), but the host calls it as \
+                 func(f64, i32, i32) -> i32",
+                "doesn't export its memory as `memory`",
+                "needs ABI version 99, but the host only supports up to 1",
+            ]
+        );
+
+        assert!(inspect(b"\0asm\x01\0\0\0\x0b").is_err());
+    }
+}
@@ -0,0 +1,135 @@
+use crate::font;
+use crate::plugin::Rect;
+
+/// Software drawing into an RGBA frame that the host owns, for frames and
+/// overlays the guest has no part in.
+pub struct Canvas<'a> {
+    data: &'a mut [u8],
+    size: Rect,
+}
+
+impl<'a> Canvas<'a> {
+    /// `data` holds `size.width * size.height` tightly packed RGBA pixels.
+    pub fn new(data: &'a mut [u8], size: &Rect) -> Canvas<'a> {
+        assert_eq!(data.len(), size.width as usize * size.height as usize * 4);
+        Canvas {
+            data,
+            size: size.clone(),
+        }
+    }
+
+    pub fn fill(&mut self, color: [u8; 4]) {
+        for px in self.data.chunks_exact_mut(4) {
+            px.copy_from_slice(&color);
+        }
+    }
+
+    /// Fills a rectangle, clipped to the canvas.
+    pub fn fill_rect(&mut self, x: i32, y: i32, width: i32, height: i32, color: [u8; 4]) {
+        self.for_each_pixel(x, y, width, height, |px| px.copy_from_slice(&color));
+    }
+
+    /// Like `fill_rect`, but blends `color` over what is there by its alpha,
+    /// leaving the alpha of the canvas alone.
+    pub fn blend_rect(&mut self, x: i32, y: i32, width: i32, height: i32, color: [u8; 4]) {
+        let alpha = color[3] as u32;
+        self.for_each_pixel(x, y, width, height, |px| {
+            for (c, &value) in px[..3].iter_mut().zip(&color) {
+                *c = ((value as u32 * alpha + *c as u32 * (255 - alpha) + 127) / 255) as u8;
+            }
+        });
+    }
+
+    fn for_each_pixel(
+        &mut self,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+        mut f: impl FnMut(&mut [u8]),
+    ) {
+        let left = x.clamp(0, self.size.width) as usize;
+        let right = x.saturating_add(width).clamp(0, self.size.width) as usize;
+        let top = y.clamp(0, self.size.height);
+        let bottom = y.saturating_add(height).clamp(0, self.size.height);
+        for row in top..bottom {
+            let start = row as usize * self.size.width as usize;
+            for px in self.data[(start + left) * 4..(start + right) * 4].chunks_exact_mut(4) {
+                f(px);
+            }
+        }
+    }
+
+    /// Draws one line of text with its top-left corner at `x, y`, each font
+    /// pixel being `scale` pixels big. Returns the x after the last glyph.
+    pub fn draw_text(&mut self, x: i32, y: i32, text: &str, scale: i32, color: [u8; 4]) -> i32 {
+        let mut x = x;
+        for c in text.chars() {
+            for (row, bits) in font::glyph(c).iter().enumerate() {
+                for col in 0..font::WIDTH {
+                    if bits & (0x80 >> col) != 0 {
+                        self.fill_rect(
+                            x + col as i32 * scale,
+                            y + row as i32 * scale,
+                            scale,
+                            scale,
+                            color,
+                        );
+                    }
+                }
+            }
+            x += font::WIDTH as i32 * scale;
+        }
+        x
+    }
+}
+
+/// Breaks `text` into lines of at most `columns` characters, preferring to
+/// break at spaces.
+pub fn wrap(text: &str, columns: usize) -> Vec<String> {
+    let columns = columns.max(1);
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split(' ') {
+            let mut word = word;
+            while !word.is_empty() {
+                let used = line.chars().count();
+                let space = if used == 0 { 0 } else { 1 };
+                let len = word.chars().count();
+                if used + space + len <= columns {
+                    if space == 1 {
+                        line.push(' ');
+                    }
+                    line.push_str(word);
+                    word = "";
+                } else if used > 0 {
+                    lines.push(std::mem::take(&mut line));
+                } else {
+                    let split = word
+                        .char_indices()
+                        .nth(columns)
+                        .map_or(word.len(), |(i, _)| i);
+                    lines.push(word[..split].to_string());
+                    word = &word[split..];
+                }
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(
+            wrap("unreachable executed\nat render", 12),
+            ["unreachable", "executed", "at render"]
+        );
+        assert_eq!(wrap("0123456789", 4), ["0123", "4567", "89"]);
+    }
+}
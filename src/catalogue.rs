@@ -0,0 +1,318 @@
+use crate::abi::{self, Inspection};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Whether the host can run a module.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum Status {
+    Ok,
+    /// Valid wasm that doesn't follow the guest ABI.
+    Incompatible,
+    /// Unreadable, or not wasm at all.
+    Broken,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Status::Ok => "ok",
+            Status::Incompatible => "incompatible",
+            Status::Broken => "broken",
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct Entry {
+    pub name: String,
+    /// The module itself, also for packages.
+    pub path: PathBuf,
+    pub size: u64,
+    /// SHA-1 of the module, in hex.
+    pub hash: String,
+    pub status: Status,
+    /// Why the module can't run.
+    pub problems: Vec<String>,
+    /// What the module offers and needs, unless it's broken.
+    pub inspection: Option<Inspection>,
+}
+
+/// The demos found in a set of directories. Each `.wasm` file is a demo
+/// named after the file, and each subdirectory with a `demo.wasm` in it is
+/// a package named after the directory, which may bring other files along.
+#[derive(Debug, Default)]
+pub struct Catalogue {
+    pub entries: Vec<Entry>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Cache {
+    modules: Vec<CachedModule>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedModule {
+    modified: SystemTime,
+    entry: Entry,
+}
+
+impl Catalogue {
+    /// Scans `dirs`, inspecting only modules that changed since they were
+    /// recorded in `cache`, which is updated afterwards.
+    pub fn scan(dirs: &[PathBuf], cache: Option<&Path>) -> anyhow::Result<Catalogue> {
+        // A missing or unreadable cache just means inspecting everything.
+        let old: Cache = cache
+            .and_then(|path| std::fs::read(path).ok())
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        let mut old: HashMap<PathBuf, CachedModule> = old
+            .modules
+            .into_iter()
+            .map(|module| (module.entry.path.clone(), module))
+            .collect();
+
+        let mut new = Cache::default();
+        let mut catalogue = Catalogue::default();
+        for dir in dirs {
+            for (name, path) in find_modules(dir)? {
+                let cached = old.remove(&path);
+                let module = scan_module(name, path, cached);
+                catalogue.entries.push(module.entry.clone());
+                new.modules.push(module);
+            }
+        }
+
+        if let Some(path) = cache {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            std::fs::write(path, serde_json::to_vec(&new)?)
+                .with_context(|| format!("writing {}", path.display()))?;
+        }
+        Ok(catalogue)
+    }
+
+    /// One line per module, with the problems of those that can't run
+    /// indented below them.
+    pub fn write_table(&self, mut out: impl Write) -> std::io::Result<()> {
+        let rows: Vec<[String; 5]> = self
+            .entries
+            .iter()
+            .map(|entry| {
+                let inspection = entry.inspection.as_ref();
+                [
+                    entry.name.clone(),
+                    entry.status.to_string(),
+                    inspection
+                        .and_then(|i| i.metadata.get("title").cloned())
+                        .unwrap_or_default(),
+                    format!("{} KiB", entry.size.div_ceil(1024)),
+                    entry.path.display().to_string(),
+                ]
+            })
+            .collect();
+        let header = ["NAME", "STATUS", "TITLE", "SIZE", "PATH"].map(String::from);
+        let mut widths = [0; 5];
+        for row in std::iter::once(&header).chain(&rows) {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+        let write_row = |out: &mut dyn Write, row: &[String; 5]| {
+            let line: Vec<String> = row
+                .iter()
+                .zip(widths)
+                .map(|(cell, width)| format!("{cell:width$}"))
+                .collect();
+            writeln!(out, "{}", line.join("  ").trim_end())
+        };
+        write_row(&mut out, &header)?;
+        for (row, entry) in rows.iter().zip(&self.entries) {
+            write_row(&mut out, row)?;
+            for problem in &entry.problems {
+                writeln!(out, "    {problem}")?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_json(&self, mut out: impl Write) -> anyhow::Result<()> {
+        serde_json::to_writer_pretty(&mut out, &self.entries)?;
+        Ok(writeln!(out)?)
+    }
+}
+
+/// Where `list` keeps its cache unless told otherwise.
+pub fn default_cache_path() -> Option<PathBuf> {
+    let dir = if cfg!(windows) {
+        std::env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else {
+        std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+    };
+    dir.map(|dir| dir.join("demo").join("catalogue.json"))
+}
+
+fn find_modules(dir: &Path) -> anyhow::Result<Vec<(String, PathBuf)>> {
+    let mut modules = Vec::new();
+    let read_dir = std::fs::read_dir(dir).with_context(|| format!("scanning {}", dir.display()))?;
+    for entry in read_dir {
+        let path = entry?.path();
+        let package = path.join("demo.wasm");
+        if path.is_dir() && package.is_file() {
+            modules.push((module_name(&package), package));
+        } else if path.is_file() && path.extension().is_some_and(|ext| ext == "wasm") {
+            modules.push((module_name(&path), path));
+        }
+    }
+    modules.sort();
+    Ok(modules)
+}
+
+/// What a module is called: the directory of a package's `demo.wasm`, or
+/// the file name of any other module without its extension.
+///
+/// ```
+/// use demo::catalogue::module_name;
+/// use std::path::Path;
+///
+/// assert_eq!(module_name(Path::new("demos/cube/demo.wasm")), "cube");
+/// assert_eq!(module_name(Path::new("demos/sdf.wasm")), "sdf");
+/// ```
+pub fn module_name(path: &Path) -> String {
+    let package = path.file_name().is_some_and(|name| name == "demo.wasm");
+    let name = path
+        .parent()
+        .and_then(Path::file_name)
+        .filter(|_| package)
+        .or_else(|| path.file_stem());
+    name.unwrap_or_default().to_string_lossy().to_string()
+}
+
+fn scan_module(name: String, path: PathBuf, cached: Option<CachedModule>) -> CachedModule {
+    let broken = |name: String, path: PathBuf, error: String| CachedModule {
+        modified: SystemTime::UNIX_EPOCH,
+        entry: Entry {
+            name,
+            path,
+            size: 0,
+            hash: String::new(),
+            status: Status::Broken,
+            problems: vec![error],
+            inspection: None,
+        },
+    };
+    let (modified, size) = match std::fs::metadata(&path) {
+        Ok(metadata) => (
+            metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+            metadata.len(),
+        ),
+        Err(error) => return broken(name, path, error.to_string()),
+    };
+    if let Some(cached) = cached {
+        if cached.modified == modified && cached.entry.size == size {
+            return cached;
+        }
+    }
+
+    let bytes = match std::fs::read(&path) {
+        Ok(bytes) => bytes,
+        Err(error) => return broken(name, path, error.to_string()),
+    };
+    let hash = sha1_hex(&bytes);
+    let (status, problems, inspection) = match abi::inspect(&bytes) {
+        Ok(inspection) if inspection.problems.is_empty() => (Status::Ok, vec![], Some(inspection)),
+        Ok(inspection) => (
+            Status::Incompatible,
+            inspection.problems.clone(),
+            Some(inspection),
+        ),
+        Err(error) => (Status::Broken, vec![format!("{error:#}")], None),
+    };
+    CachedModule {
+        modified,
+        entry: Entry {
+            name,
+            path,
+            size,
+            hash,
+            status,
+            problems,
+            inspection,
+        },
+    }
+}
+
+pub fn sha1_hex(bytes: &[u8]) -> String {
+    Sha1::digest(bytes)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_scan() {
+        let dir = std::env::temp_dir().join(format!("demo-catalogue-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let demos = dir.join("demos");
+        std::fs::create_dir_all(demos.join("cube")).unwrap();
+        let module = wat::parse_str(
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "render") (param f64 i32 i32) (result i32) i32.const 0)
+                (@custom "demo" "title = Cube"))"#,
+        )
+        .unwrap();
+        std::fs::write(demos.join("cube/demo.wasm"), &module).unwrap();
+        std::fs::write(
+            demos.join("empty.wasm"),
+            wat::parse_str("(module)").unwrap(),
+        )
+        .unwrap();
+        std::fs::write(demos.join("garbage.wasm"), b"not wasm").unwrap();
+        std::fs::write(demos.join("notes.txt"), b"not a demo").unwrap();
+
+        let cache = dir.join("cache/catalogue.json");
+        let catalogue = Catalogue::scan(std::slice::from_ref(&demos), Some(&cache)).unwrap();
+        let summary: Vec<(&str, Status)> = catalogue
+            .entries
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.status))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("cube", Status::Ok),
+                ("empty", Status::Incompatible),
+                ("garbage", Status::Broken),
+            ]
+        );
+        assert_eq!(catalogue.entries[0].hash, sha1_hex(&module));
+        assert_eq!(sha1_hex(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+
+        let mut table = Vec::new();
+        catalogue.write_table(&mut table).unwrap();
+        let table = String::from_utf8(table).unwrap();
+        assert!(table.starts_with("NAME     STATUS        TITLE  SIZE   PATH\n"));
+        assert!(table.contains("\ncube     ok            Cube   1 KiB  "));
+        assert!(table.contains("\n    doesn't export `render`\n"));
+
+        let cached = Catalogue::scan(&[demos], Some(&cache)).unwrap();
+        assert_eq!(cached.entries, catalogue.entries);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,396 @@
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use demo::pacing::{self, Wait};
+use demo::plugin::{self, Profiler};
+use demo::postprocess::Filter;
+use demo::scale::RenderScale;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+#[derive(Debug, Parser)]
+#[command(about = "Runs wasm demos")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Show a demo in a window.
+    Run(RunArgs),
+    /// Render frames of a demo to PNG files.
+    Render(RenderArgs),
+    /// Save one frame as a PNG image, possibly bigger than the demo's size.
+    Screenshot(ScreenshotArgs),
+    /// Step through a demo's frames and look at its memory from a console.
+    Debug(DebugArgs),
+    /// Feed a demo what was recorded with `run --record` and check that it
+    /// renders the same frames. Exits with status 4 if any differ.
+    Replay(ReplayArgs),
+    /// Render a demo in real time and serve the frames over the network.
+    Stream(StreamArgs),
+    /// Time how long a demo takes to render frames.
+    Bench(BenchArgs),
+    /// Print a module's exports, imports, ABI version and metadata.
+    Inspect(InspectArgs),
+    /// Check that modules follow the guest ABI and can be loaded. Exits with
+    /// status 3 if any can't.
+    Validate(ValidateArgs),
+    /// List the demos in some directories.
+    List(ListArgs),
+    /// Write a single HTML page that plays a demo in a browser, with the
+    /// module embedded in it.
+    Export(ExportArgs),
+}
+
+/// How to load a demo and set it up.
+#[derive(Debug, Args)]
+pub struct DemoArgs {
+    /// The wasm module to run.
+    pub module: PathBuf,
+    /// Preferred width in logical pixels; the demo may pick another.
+    /// Defaults to the config file's, or 640.
+    #[arg(long)]
+    pub width: Option<i32>,
+    /// Preferred height in logical pixels; the demo may pick another.
+    /// Defaults to the config file's, or 480.
+    #[arg(long)]
+    pub height: Option<i32>,
+    /// Set a demo parameter, e.g. `--param hue_speed=20`.
+    #[arg(long = "param", value_name = "NAME=VALUE", value_parser = parse_param)]
+    pub params: Vec<(String, f64)>,
+    /// Load DWARF debug info to report guest traps with source locations.
+    #[arg(long)]
+    pub debug_info: bool,
+    /// Emit symbols of the JIT-compiled guest for `perf`.
+    #[arg(long, value_enum)]
+    pub profile: Option<ProfileFormat>,
+    /// Read settings from this file instead of the default config file.
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+    /// Render at this fraction of the output size and scale up, or `auto`
+    /// to pick whatever holds the config file's target frame time.
+    #[arg(long, value_name = "SCALE")]
+    pub render_scale: Option<RenderScale>,
+    /// How to scale up frames rendered at a smaller scale.
+    #[arg(long, value_enum)]
+    pub scale_filter: Option<ScaleFilter>,
+    /// Draw the HUD overlay with frame times, parameters and the log on
+    /// frames.
+    #[arg(long)]
+    pub hud: bool,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum ScaleFilter {
+    Nearest,
+    Bilinear,
+    Lanczos,
+}
+
+impl From<ScaleFilter> for Filter {
+    fn from(filter: ScaleFilter) -> Filter {
+        match filter {
+            ScaleFilter::Nearest => Filter::Nearest,
+            ScaleFilter::Bilinear => Filter::Bilinear,
+            ScaleFilter::Lanczos => Filter::Lanczos,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum FrameWait {
+    Sleep,
+    Yield,
+}
+
+impl From<FrameWait> for Wait {
+    fn from(wait: FrameWait) -> Wait {
+        match wait {
+            FrameWait::Sleep => Wait::Sleep,
+            FrameWait::Yield => Wait::Yield,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum ProfileFormat {
+    PerfMap,
+    JitDump,
+}
+
+impl DemoArgs {
+    pub fn runtime_options(&self) -> plugin::RuntimeOptions {
+        let mut options = plugin::RuntimeOptions::default();
+        options.debug_info = self.debug_info;
+        options.profiler = match self.profile {
+            None => Profiler::None,
+            Some(ProfileFormat::PerfMap) => Profiler::PerfMap,
+            Some(ProfileFormat::JitDump) => Profiler::JitDump,
+        };
+        options
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct RunArgs {
+    #[command(flatten)]
+    pub demo: DemoArgs,
+    /// Cover the whole screen instead of opening a window.
+    #[arg(long)]
+    pub fullscreen: bool,
+    /// How many seconds of demo time pass per second. Defaults to the
+    /// config file's, or 1.
+    #[arg(long)]
+    pub speed: Option<f64>,
+    /// Whether to wait for the display's refresh between frames. Defaults
+    /// to the config file's, or true.
+    #[arg(long, value_name = "BOOL")]
+    pub vsync: Option<bool>,
+    /// Frames per second to stay under, or 0 for no cap. Defaults to the
+    /// config file's, or 0; with `--vsync false` too, frames are uncapped.
+    #[arg(long, value_name = "FPS", value_parser = parse_max_fps)]
+    pub max_fps: Option<f64>,
+    /// How to wait for the next frame under `--max-fps`.
+    #[arg(long, value_enum)]
+    pub frame_wait: Option<FrameWait>,
+    /// Whether demo time keeps up with the clock by skipping frames when
+    /// they run late, instead of moving on one frame time per frame.
+    /// Defaults to the config file's, or true.
+    #[arg(long, value_name = "BOOL")]
+    pub frame_skip: Option<bool>,
+    /// Demo time to start at, in seconds.
+    #[arg(long, default_value_t = 0.0)]
+    pub start: f64,
+    /// Accept remote control commands over HTTP on this address.
+    #[arg(long, value_name = "ADDR")]
+    pub remote: Option<SocketAddr>,
+    /// Read debug console commands from standard input while the demo runs.
+    #[arg(long)]
+    pub console: bool,
+    /// Record input, parameter changes, resizes and frame times to FILE,
+    /// for `replay`.
+    #[arg(long, value_name = "FILE")]
+    pub record: Option<PathBuf>,
+}
+
+/// Options for rendering without a window.
+#[derive(Debug, Args)]
+pub struct HeadlessArgs {
+    #[command(flatten)]
+    pub demo: DemoArgs,
+    /// DPI of the imaginary display.
+    #[arg(long, default_value_t = plugin::BASE_DPI)]
+    pub dpi: i32,
+}
+
+#[derive(Debug, Args)]
+pub struct RenderArgs {
+    #[command(flatten)]
+    pub headless: HeadlessArgs,
+    /// Directory to write `frame-00000.png` and so on to.
+    #[arg(long, short)]
+    pub out: PathBuf,
+    #[arg(long, default_value_t = 1)]
+    pub frames: u32,
+    /// Frames per second of demo time.
+    #[arg(long, default_value_t = 30.0, value_parser = parse_fps)]
+    pub fps: f64,
+    /// Demo time of the first frame, in seconds.
+    #[arg(long, default_value_t = 0.0)]
+    pub start: f64,
+    /// Also write the guest's profiling spans as a Chrome trace, up to the
+    /// first million or so.
+    #[arg(long, value_name = "FILE")]
+    pub trace: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct ScreenshotArgs {
+    #[command(flatten)]
+    pub headless: HeadlessArgs,
+    #[arg(long, short)]
+    pub out: PathBuf,
+    /// Demo time of the frame, in seconds.
+    #[arg(long, default_value_t = 0.0)]
+    pub time: f64,
+    /// How many times bigger than the demo's size to capture, up to its
+    /// maximum size; `screenshot_scale` in the config file by default.
+    #[arg(long)]
+    pub scale: Option<f64>,
+}
+
+#[derive(Debug, Args)]
+pub struct DebugArgs {
+    #[command(flatten)]
+    pub headless: HeadlessArgs,
+    /// Demo time to start at, in seconds.
+    #[arg(long, default_value_t = 0.0)]
+    pub start: f64,
+    /// Frames per second of demo time, for stepping.
+    #[arg(long, default_value_t = 60.0, value_parser = parse_fps)]
+    pub fps: f64,
+}
+
+#[derive(Debug, Args)]
+pub struct ReplayArgs {
+    /// The wasm module to replay, usually the one that was recorded.
+    pub module: PathBuf,
+    /// The file written by `run --record`.
+    pub replay: PathBuf,
+    /// Also write the frames to `frame-00000.png` and so on in this
+    /// directory.
+    #[arg(long, short)]
+    pub out: Option<PathBuf>,
+    /// Load DWARF debug info to report guest traps with source locations.
+    #[arg(long)]
+    pub debug_info: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct StreamArgs {
+    #[command(flatten)]
+    pub headless: HeadlessArgs,
+    /// Serve a page, MJPEG and PNG streams over HTTP on this address.
+    #[arg(long, value_name = "ADDR")]
+    pub http: Option<SocketAddr>,
+    /// Serve raw RGBA frames over TCP on this address.
+    #[arg(long, value_name = "ADDR")]
+    pub raw: Option<SocketAddr>,
+    #[arg(long, default_value_t = 30.0, value_parser = parse_fps)]
+    pub fps: f64,
+    /// JPEG quality, from 1 to 100.
+    #[arg(long, default_value_t = 80, value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub quality: u8,
+}
+
+#[derive(Debug, Args)]
+pub struct BenchArgs {
+    #[command(flatten)]
+    pub headless: HeadlessArgs,
+    #[arg(long, default_value_t = 100)]
+    pub frames: u32,
+    /// Frames rendered before timing starts.
+    #[arg(long, default_value_t = 5)]
+    pub warmup: u32,
+    /// Also print where the guest spent its time in the last frame.
+    #[arg(long)]
+    pub spans: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct InspectArgs {
+    pub module: PathBuf,
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ValidateArgs {
+    #[arg(required = true)]
+    pub modules: Vec<PathBuf>,
+    /// Read capability policies from this file instead of the default
+    /// config file.
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct ListArgs {
+    /// Directories to scan; the current one by default.
+    pub dirs: Vec<PathBuf>,
+    #[arg(long)]
+    pub json: bool,
+    /// Inspect every module instead of trusting the cache.
+    #[arg(long)]
+    pub no_cache: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    /// The wasm module, or the package directory it is the `demo.wasm` of.
+    pub module: PathBuf,
+    /// Where to write the page; the demo's name with `.html` in the current
+    /// directory by default.
+    #[arg(long, short)]
+    pub out: Option<PathBuf>,
+    /// Preferred width in logical pixels; the demo may pick another.
+    /// Defaults to the config file's, or 640.
+    #[arg(long)]
+    pub width: Option<i32>,
+    /// Preferred height in logical pixels; the demo may pick another.
+    /// Defaults to the config file's, or 480.
+    #[arg(long)]
+    pub height: Option<i32>,
+    /// Set a demo parameter, e.g. `--param hue_speed=20`.
+    #[arg(long = "param", value_name = "NAME=VALUE", value_parser = parse_param)]
+    pub params: Vec<(String, f64)>,
+    /// Read settings from this file instead of the default config file.
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+}
+
+fn parse_max_fps(arg: &str) -> Result<f64, String> {
+    let fps = arg
+        .parse()
+        .map_err(|_| format!("{arg:?} is not a number"))?;
+    pacing::check_max_fps(fps)
+}
+
+/// Frame rates that frames are spaced out by, unlike caps, can't be 0.
+fn parse_fps(arg: &str) -> Result<f64, String> {
+    match arg.parse::<f64>() {
+        Ok(fps) if fps.is_finite() && fps > 0.0 => Ok(fps),
+        _ => Err(format!("expected a frame rate above 0, got {arg:?}")),
+    }
+}
+
+fn parse_param(arg: &str) -> Result<(String, f64), String> {
+    let (name, value) = arg
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=VALUE, got {arg:?}"))?;
+    let value = value
+        .parse()
+        .map_err(|_| format!("{value:?} is not a number"))?;
+    Ok((name.to_string(), value))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+
+        let cli = Cli::try_parse_from([
+            "demo",
+            "run",
+            "sdf.wasm",
+            "--param",
+            "hue_speed=20",
+            "--speed",
+            "0.5",
+            "--render-scale",
+            "auto",
+        ])
+        .unwrap();
+        let Command::Run(run) = cli.command else {
+            panic!("parsed {:?}", cli.command);
+        };
+        assert_eq!(run.demo.params, [("hue_speed".to_string(), 20.0)]);
+        assert_eq!(run.speed, Some(0.5));
+        assert_eq!(run.demo.width, None);
+        assert_eq!(run.demo.render_scale, Some(RenderScale::Auto));
+
+        let error = Cli::try_parse_from(["demo", "run", "sdf.wasm", "--param", "hue"]).unwrap_err();
+        assert!(error.to_string().contains("expected NAME=VALUE"));
+        for args in [
+            ["demo", "run", "sdf.wasm", "--max-fps", "inf"],
+            ["demo", "run", "sdf.wasm", "--max-fps", "-1"],
+            ["demo", "stream", "sdf.wasm", "--fps", "0"],
+        ] {
+            assert!(Cli::try_parse_from(args).is_err(), "{args:?}");
+        }
+    }
+}
@@ -0,0 +1,459 @@
+use crate::pacing::{self, Pacer, Wait};
+use crate::plugin::{self, Capability, Policy, Rect};
+use crate::postprocess::{Filter, Pass};
+use crate::scale::{RenderScale, Scaler};
+use anyhow::Context;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The user's configuration file, e.g.
+///
+/// ```toml
+/// [defaults]
+/// size = [800, 600]
+/// clear_color = "#000000"
+///
+/// # Matched by module name, like in `list`...
+/// [demo.sdf]
+/// vsync = false
+/// params = { hue_speed = 20 }
+/// capabilities = ["logging"]
+///
+/// # ...or by the SHA-1 of the module, which wins over the name.
+/// [demo.0123456789abcdef0123456789abcdef01234567]
+/// speed = 0.5
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+#[non_exhaustive]
+pub struct Config {
+    pub defaults: Profile,
+    #[serde(rename = "demo")]
+    pub demos: BTreeMap<String, Profile>,
+}
+
+/// Settings from one section of the config file. Anything left out comes
+/// from the sections before it.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+#[non_exhaustive]
+pub struct Profile {
+    pub min_size: Option<[i32; 2]>,
+    /// Preferred size in logical pixels.
+    pub size: Option<[i32; 2]>,
+    pub max_size: Option<[i32; 2]>,
+    /// `#rrggbb`, drawn behind the demo.
+    pub clear_color: Option<Color>,
+    pub vsync: Option<bool>,
+    /// Frames per second to stay under, or 0 for as many as there can be.
+    #[serde(deserialize_with = "max_fps")]
+    pub max_fps: Option<f64>,
+    /// How to wait for the next frame under `max_fps`.
+    pub frame_wait: Option<Wait>,
+    /// Whether demo time keeps up with the clock when frames run late, by
+    /// skipping over the frames they make no time for. Otherwise each frame
+    /// moves the demo on by one frame time, and late frames slow it down.
+    pub frame_skip: Option<bool>,
+    pub speed: Option<f64>,
+    pub params: BTreeMap<String, f64>,
+    /// What the demo may use; see `plugin::Capability`.
+    pub capabilities: Option<Vec<Capability>>,
+    /// Applied to every frame before it is shown or saved.
+    pub postprocess: Option<Vec<Pass>>,
+    /// A fraction of the output size to render at, or `"auto"`.
+    pub render_scale: Option<RenderScale>,
+    /// How frames rendered at a smaller scale are scaled up.
+    pub scale_filter: Option<Filter>,
+    /// Milliseconds per frame that `render_scale = "auto"` aims for.
+    #[serde(deserialize_with = "target_frame_time")]
+    pub target_frame_time: Option<f64>,
+    /// How many times bigger than the window screenshots are.
+    pub screenshot_scale: Option<f64>,
+    /// Where screenshots are saved.
+    pub screenshot_dir: Option<PathBuf>,
+    /// Show the HUD overlay from the start; F3 toggles it in a window.
+    pub hud: Option<bool>,
+}
+
+/// What a demo runs with once the config file and the command line are
+/// taken into account.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct Settings {
+    pub min_size: Rect,
+    pub size: Rect,
+    pub max_size: Rect,
+    pub clear_color: Color,
+    pub vsync: bool,
+    pub max_fps: f64,
+    pub frame_wait: Wait,
+    pub frame_skip: bool,
+    pub speed: f64,
+    pub params: BTreeMap<String, f64>,
+    pub policy: Policy,
+    pub postprocess: Vec<Pass>,
+    pub render_scale: RenderScale,
+    pub scale_filter: Filter,
+    pub target_frame_time: Duration,
+    pub screenshot_scale: f64,
+    pub screenshot_dir: PathBuf,
+    pub hud: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            min_size: plugin::MIN_SIZE,
+            size: Rect {
+                width: 640,
+                height: 480,
+            },
+            max_size: plugin::MAX_SIZE,
+            clear_color: Color::WHITE,
+            vsync: true,
+            max_fps: 0.0,
+            frame_wait: Wait::default(),
+            frame_skip: true,
+            speed: 1.0,
+            params: BTreeMap::new(),
+            policy: Policy::default(),
+            postprocess: Vec::new(),
+            render_scale: RenderScale::default(),
+            scale_filter: Filter::Bilinear,
+            target_frame_time: Duration::from_secs(1) / 60,
+            screenshot_scale: 1.0,
+            screenshot_dir: PathBuf::from("."),
+            hud: false,
+        }
+    }
+}
+
+impl Settings {
+    fn apply(&mut self, profile: &Profile) {
+        let rect = |[width, height]: [i32; 2]| Rect { width, height };
+        if let Some(size) = profile.min_size {
+            self.min_size = rect(size);
+        }
+        if let Some(size) = profile.size {
+            self.size = rect(size);
+        }
+        if let Some(size) = profile.max_size {
+            self.max_size = rect(size);
+        }
+        if let Some(color) = profile.clear_color {
+            self.clear_color = color;
+        }
+        if let Some(vsync) = profile.vsync {
+            self.vsync = vsync;
+        }
+        if let Some(fps) = profile.max_fps {
+            self.max_fps = fps;
+        }
+        if let Some(wait) = profile.frame_wait {
+            self.frame_wait = wait;
+        }
+        if let Some(skip) = profile.frame_skip {
+            self.frame_skip = skip;
+        }
+        if let Some(speed) = profile.speed {
+            self.speed = speed;
+        }
+        self.params.extend(profile.params.clone());
+        if let Some(capabilities) = &profile.capabilities {
+            self.policy = Policy::allow(capabilities.iter().copied());
+        }
+        if let Some(passes) = &profile.postprocess {
+            self.postprocess = passes.clone();
+        }
+        if let Some(scale) = profile.render_scale {
+            self.render_scale = scale;
+        }
+        if let Some(filter) = profile.scale_filter {
+            self.scale_filter = filter;
+        }
+        if let Some(time) = profile.target_frame_time.and_then(frame_time) {
+            self.target_frame_time = time;
+        }
+        if let Some(scale) = profile.screenshot_scale {
+            self.screenshot_scale = scale;
+        }
+        if let Some(dir) = &profile.screenshot_dir {
+            self.screenshot_dir = dir.clone();
+        }
+        if let Some(hud) = profile.hud {
+            self.hud = hud;
+        }
+    }
+
+    pub fn scaler(&self) -> Scaler {
+        Scaler::new(self.render_scale, self.scale_filter, self.target_frame_time)
+    }
+
+    /// Paces frames to `max_fps`. Without a cap, a frame is one
+    /// `target_frame_time` long where demo time doesn't follow the clock.
+    pub fn pacer(&self) -> Pacer {
+        Pacer::new(self.max_fps, self.frame_wait, self.target_frame_time)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+}
+
+impl Color {
+    pub const WHITE: Color = Color {
+        r: 1.0,
+        g: 1.0,
+        b: 1.0,
+    };
+}
+
+impl TryFrom<String> for Color {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        let error = || format!("expected a colour like \"#rrggbb\", got {text:?}");
+        let hex = text.strip_prefix('#').ok_or_else(error)?;
+        if hex.len() != 6 {
+            return Err(error());
+        }
+        let channel = |i: usize| {
+            hex.get(i..i + 2)
+                .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                .map(|value| value as f32 / 255.0)
+                .ok_or_else(error)
+        };
+        Ok(Color {
+            r: channel(0)?,
+            g: channel(2)?,
+            b: channel(4)?,
+        })
+    }
+}
+
+impl Config {
+    /// Reads `path`, or the default config file if there is one.
+    pub fn load(path: Option<&Path>) -> anyhow::Result<Config> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match default_path() {
+                Some(path) if path.is_file() => path,
+                _ => return Ok(Config::default()),
+            },
+        };
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("reading {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("parsing {}", path.display()))
+    }
+
+    /// The settings for the module called `name` whose SHA-1 is `hash`.
+    ///
+    /// ```
+    /// let config: demo::Config = toml::from_str(
+    ///     r#"
+    ///     [defaults]
+    ///     size = [800, 600]
+    ///     [demo.sdf]
+    ///     vsync = false
+    ///     "#,
+    /// )?;
+    /// let settings = config.settings("sdf", "");
+    /// assert_eq!((settings.size.width, settings.vsync), (800, false));
+    /// # Ok::<(), toml::de::Error>(())
+    /// ```
+    pub fn settings(&self, name: &str, hash: &str) -> Settings {
+        let mut settings = Settings::default();
+        settings.apply(&self.defaults);
+        for key in [name, hash] {
+            if let Some(profile) = self.demos.get(key) {
+                settings.apply(profile);
+            }
+        }
+        settings
+    }
+}
+
+/// The `demo.toml` of a package, next to its `demo.wasm`:
+///
+/// ```toml
+/// capabilities = ["logging", "clock"]
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+#[non_exhaustive]
+pub struct Manifest {
+    /// What the package needs. It gets no other capabilities even where the
+    /// config file allows them, and all that the config allows if this is
+    /// left out.
+    pub capabilities: Option<Vec<Capability>>,
+}
+
+impl Manifest {
+    /// Reads the manifest of the package `module` is the `demo.wasm` of.
+    /// Other modules, and packages without a manifest, get an empty one.
+    pub fn for_module(module: &Path) -> anyhow::Result<Manifest> {
+        let path = module.with_file_name("demo.toml");
+        if module.file_name().is_some_and(|name| name != "demo.wasm") || !path.is_file() {
+            return Ok(Manifest::default());
+        }
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("reading {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("parsing {}", path.display()))
+    }
+
+    pub fn policy(&self) -> Policy {
+        match &self.capabilities {
+            Some(capabilities) => Policy::allow(capabilities.iter().copied()),
+            None => Policy::default(),
+        }
+    }
+}
+
+/// A frame time of `millis` milliseconds, if it is one.
+fn frame_time(millis: f64) -> Option<Duration> {
+    Duration::try_from_secs_f64(millis / 1e3)
+        .ok()
+        .filter(|time| !time.is_zero())
+}
+
+fn target_frame_time<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+    let millis = f64::deserialize(deserializer)?;
+    match frame_time(millis) {
+        Some(_) => Ok(Some(millis)),
+        None => Err(D::Error::custom(format!(
+            "target_frame_time: expected a number of milliseconds above 0, got {millis}"
+        ))),
+    }
+}
+
+fn max_fps<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+    let fps = f64::deserialize(deserializer)?;
+    pacing::check_max_fps(fps)
+        .map(Some)
+        .map_err(|error| D::Error::custom(format!("max_fps: {error}")))
+}
+
+/// Where the config file is unless `--config` says otherwise.
+pub fn default_path() -> Option<PathBuf> {
+    let dir = if cfg!(windows) {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+    };
+    dir.map(|dir| dir.join("demo").join("config.toml"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::postprocess::Filter;
+
+    #[test]
+    fn test_config() {
+        let config: Config = toml::from_str(
+            r##"
+            [defaults]
+            size = [800, 600]
+            clear_color = "#000000"
+            screenshot_scale = 2
+
+            [demo.sdf]
+            vsync = false
+            hud = true
+            speed = 2.0
+            params = { hue_speed = 20 }
+
+            [demo.abc123]
+            speed = 0.5
+            capabilities = []
+            render_scale = "auto"
+            target_frame_time = 20
+            max_fps = 30
+            frame_wait = "yield"
+            frame_skip = false
+
+            [[demo.sdf.postprocess]]
+            pass = "scale"
+            factor = 2
+            filter = "lanczos"
+
+            [[demo.sdf.postprocess]]
+            pass = "scanlines"
+            intensity = 0.3
+            "##,
+        )
+        .unwrap();
+
+        let other = config.settings("cube", "def456");
+        assert_eq!(
+            other.size,
+            Rect {
+                width: 800,
+                height: 600
+            }
+        );
+        assert_eq!(other.min_size, plugin::MIN_SIZE);
+        assert_eq!(
+            other.clear_color,
+            Color {
+                r: 0.0,
+                g: 0.0,
+                b: 0.0
+            }
+        );
+        assert!(other.vsync);
+        assert_eq!(other.screenshot_scale, 2.0);
+
+        let sdf = config.settings("sdf", "def456");
+        assert!(!sdf.vsync);
+        assert!(sdf.hud && !other.hud);
+        assert_eq!(sdf.speed, 2.0);
+        assert_eq!(sdf.params["hue_speed"], 20.0);
+        assert_eq!(config.settings("sdf", "abc123").speed, 0.5);
+        assert!(sdf.policy.allows(Capability::Clock));
+        let auto = config.settings("sdf", "abc123");
+        assert_eq!(auto.render_scale, RenderScale::Auto);
+        assert_eq!(auto.target_frame_time, Duration::from_millis(20));
+        assert_eq!((auto.frame_wait, auto.frame_skip), (Wait::Yield, false));
+        assert_eq!(auto.pacer().period(), Duration::from_secs_f64(1.0 / 30.0));
+        assert_eq!(sdf.pacer().period(), Duration::from_secs(1) / 60);
+        assert_eq!(
+            sdf.postprocess,
+            [
+                Pass::Scale {
+                    factor: 2.0,
+                    filter: Filter::Lanczos
+                },
+                Pass::Scanlines { intensity: 0.3 },
+            ]
+        );
+        assert!(!config
+            .settings("sdf", "abc123")
+            .policy
+            .allows(Capability::Logging));
+
+        let error = toml::from_str::<Config>("[defaults]\nclear_color = \"red\"").unwrap_err();
+        assert!(error.to_string().contains("expected a colour"));
+        assert!(toml::from_str::<Config>("[defaults]\nfullscreen = true").is_err());
+        let error = toml::from_str::<Config>("[defaults]\nmax_fps = -1").unwrap_err();
+        assert!(error.to_string().contains("max_fps: expected a frame rate"));
+        for millis in ["-5", "0", "nan", "1e300"] {
+            let error =
+                toml::from_str::<Config>(&format!("[defaults]\ntarget_frame_time = {millis}"))
+                    .unwrap_err();
+            assert!(error.to_string().contains("target_frame_time: expected"));
+        }
+        assert!(toml::from_str::<Config>("[defaults]\nrender_scale = 2").is_err());
+        let typo = "[[defaults.postprocess]]\npass = \"gamma\"\ngama = 2";
+        assert!(toml::from_str::<Config>(typo).is_err());
+    }
+}
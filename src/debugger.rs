@@ -0,0 +1,318 @@
+use crate::playback::Playback;
+use crate::plugin::DemoRunner;
+use anyhow::Context;
+use std::fmt::Write;
+use std::io::BufRead;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver};
+
+pub const HELP: &str = "\
+pause, play          stop or resume playback
+step [N]             pause and move N frames, 1 by default; negative goes back
+seek TIME            jump to TIME seconds
+exports              list the module's exports
+globals              show the exported globals
+memory               show the memory size and where the last frame is
+dump ADDR LEN        hex-dump LEN bytes from ADDR
+rgba ADDR COUNT      show COUNT pixels from ADDR
+f32 ADDR COUNT       show COUNT floats from ADDR
+save FILE            write the last frame's raw RGBA buffer to FILE
+Addresses are decimal or 0x-prefixed hex.";
+
+/// A line typed into the debug console; `HELP` lists them.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum Command {
+    Pause,
+    Play,
+    Step(i32),
+    Seek(f64),
+    Exports,
+    Globals,
+    Memory,
+    Dump { address: usize, len: usize },
+    Rgba { address: usize, count: usize },
+    F32 { address: usize, count: usize },
+    Save(PathBuf),
+    Help,
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        // Paths may have spaces in them, so a path is the rest of the line.
+        if let Some(("save", path)) = line.trim().split_once(char::is_whitespace) {
+            return Ok(Command::Save(PathBuf::from(path.trim_start())));
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let number = |i: usize| -> Result<usize, String> {
+            let word = words[i];
+            match word.strip_prefix("0x") {
+                Some(hex) => usize::from_str_radix(hex, 16),
+                None => word.parse(),
+            }
+            .map_err(|_| format!("expected a number, got {word:?}"))
+        };
+        let command = match words.as_slice() {
+            ["pause"] => Command::Pause,
+            ["play"] => Command::Play,
+            ["step"] => Command::Step(1),
+            ["step", frames] => Command::Step(
+                frames
+                    .parse()
+                    .map_err(|_| format!("expected a frame count, got {frames:?}"))?,
+            ),
+            ["seek", time] => Command::Seek(
+                time.parse()
+                    .map_err(|_| format!("expected a time, got {time:?}"))?,
+            ),
+            ["exports"] => Command::Exports,
+            ["globals"] => Command::Globals,
+            ["memory"] => Command::Memory,
+            ["dump", _, _] => Command::Dump {
+                address: number(1)?,
+                len: number(2)?,
+            },
+            ["rgba", _, _] => Command::Rgba {
+                address: number(1)?,
+                count: number(2)?,
+            },
+            ["f32", _, _] => Command::F32 {
+                address: number(1)?,
+                count: number(2)?,
+            },
+            ["help"] => Command::Help,
+            _ => return Err(format!("unknown command {line:?}; try `help`")),
+        };
+        Ok(command)
+    }
+}
+
+/// Runs `command` and returns what to show for it. Frames are `frame_time`
+/// seconds apart when stepping. Commands that look at guest memory see it
+/// as the last frame left it.
+pub fn apply(
+    command: &Command,
+    playback: &mut Playback,
+    runner: &mut DemoRunner,
+    frame_time: f64,
+) -> anyhow::Result<String> {
+    let mut out = String::new();
+    match command {
+        Command::Pause => playback.pause(),
+        Command::Play => playback.play(),
+        Command::Step(frames) => {
+            playback.pause();
+            playback.seek(playback.time() + *frames as f64 * frame_time);
+        }
+        Command::Seek(time) => playback.seek(*time),
+        Command::Exports => {
+            for (name, ty) in runner.exports() {
+                writeln!(out, "{name}: {ty}")?;
+            }
+        }
+        Command::Globals => {
+            let globals = runner.globals();
+            if globals.is_empty() {
+                writeln!(out, "no exported globals")?;
+            }
+            for (name, value) in globals {
+                writeln!(out, "{name} = {value}")?;
+            }
+        }
+        Command::Memory => {
+            let size = runner.memory_size();
+            writeln!(out, "{size} bytes ({} pages)", size / 65536)?;
+            if let (Some(address), Ok(frame)) = (runner.frame_address(), runner.frame()) {
+                writeln!(
+                    out,
+                    "last frame: {}x{} at {address:#x}, {} bytes",
+                    frame.size.width,
+                    frame.size.height,
+                    frame.data.len()
+                )?;
+            }
+        }
+        Command::Dump { address, len } => {
+            let bytes = runner.read_memory(*address, *len)?;
+            for (i, row) in bytes.chunks(16).enumerate() {
+                let hex: Vec<String> = row.iter().map(|byte| format!("{byte:02x}")).collect();
+                let text: String = row
+                    .iter()
+                    .map(|&byte| match byte {
+                        0x20..=0x7e => byte as char,
+                        _ => '.',
+                    })
+                    .collect();
+                writeln!(
+                    out,
+                    "{:08x}  {:<47}  |{text}|",
+                    address + i * 16,
+                    hex.join(" ")
+                )?;
+            }
+        }
+        Command::Rgba { address, count } => {
+            let bytes = runner.read_memory(*address, count.saturating_mul(4))?;
+            for (i, row) in bytes.chunks(32).enumerate() {
+                let pixels: Vec<String> = row
+                    .chunks(4)
+                    .map(|p| format!("#{:02x}{:02x}{:02x}{:02x}", p[0], p[1], p[2], p[3]))
+                    .collect();
+                writeln!(out, "{:08x}  {}", address + i * 32, pixels.join(" "))?;
+            }
+        }
+        Command::F32 { address, count } => {
+            let bytes = runner.read_memory(*address, count.saturating_mul(4))?;
+            for (i, row) in bytes.chunks(32).enumerate() {
+                let floats: Vec<String> = row
+                    .chunks(4)
+                    .map(|f| f32::from_le_bytes(f.try_into().unwrap()).to_string())
+                    .collect();
+                writeln!(out, "{:08x}  {}", address + i * 32, floats.join(" "))?;
+            }
+        }
+        Command::Save(path) => {
+            let frame = runner.frame()?;
+            std::fs::write(path, frame.data)
+                .with_context(|| format!("writing {}", path.display()))?;
+            writeln!(
+                out,
+                "saved a {}x{} RGBA frame, {} bytes, to {}",
+                frame.size.width,
+                frame.size.height,
+                frame.data.len(),
+                path.display()
+            )?;
+        }
+        Command::Help => writeln!(out, "{HELP}")?,
+    }
+    if out.is_empty() {
+        let state = if playback.is_playing() {
+            "playing"
+        } else {
+            "paused"
+        };
+        writeln!(out, "{state} at {:.3} s", playback.time())?;
+    }
+    Ok(out)
+}
+
+/// Parses and runs a console `line`, returning what to show for it,
+/// including errors.
+pub fn run_line(
+    line: &str,
+    playback: &mut Playback,
+    runner: &mut DemoRunner,
+    frame_time: f64,
+) -> String {
+    match line.parse::<Command>() {
+        Ok(command) => apply(&command, playback, runner, frame_time)
+            .unwrap_or_else(|error| format!("error: {error:#}\n")),
+        Err(error) => format!("{error}\n"),
+    }
+}
+
+/// Reads console lines from standard input on a background thread, for
+/// presenters that can't wait for them.
+pub struct Console {
+    lines: Receiver<String>,
+}
+
+impl Console {
+    pub fn start() -> Console {
+        let (sender, lines) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in std::io::stdin().lock().lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Console { lines }
+    }
+
+    /// Hands the non-empty lines typed since the last call to `handle`.
+    pub fn poll(&self, mut handle: impl FnMut(&str)) {
+        while let Ok(line) = self.lines.try_recv() {
+            if !line.trim().is_empty() {
+                handle(&line);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::plugin::Rect;
+
+    #[test]
+    fn test_debugger() {
+        let mut runner = crate::create_wat(
+            r#"(module
+                (memory (export "memory") 1)
+                (global (export "frames") (mut i32) (i32.const 0))
+                (global (export "scale") f32 (f32.const 1.5))
+                (data (i32.const 16) "hi\00\00\00\00\c0\3f")
+                (func (export "render") (param f64 i32 i32) (result i32)
+                    (global.set 0 (i32.add (global.get 0) (i32.const 1)))
+                    (i32.store (i32.const 0x100) (i32.const 0xff0000ff))
+                    i32.const 0x100))"#,
+        )
+        .unwrap();
+        let mut playback = Playback::default();
+        let mut run = |line: &str, runner: &mut DemoRunner| {
+            let out = run_line(line, &mut playback, runner, 0.5);
+            if !playback.is_playing() {
+                let size = Rect {
+                    width: 2,
+                    height: 1,
+                };
+                runner.render_frame(playback.time(), &size).unwrap();
+            }
+            out
+        };
+
+        assert_eq!(run("seek 2", &mut runner), "playing at 2.000 s\n");
+        assert_eq!(run("step 3", &mut runner), "paused at 3.500 s\n");
+        assert_eq!(run("step -1", &mut runner), "paused at 3.000 s\n");
+        assert_eq!(
+            run("exports", &mut runner),
+            "memory: memory\nframes: global i32\nscale: global f32\n\
+             render: func(f64, i32, i32) -> i32\n"
+        );
+        assert_eq!(run("globals", &mut runner), "frames = 3\nscale = 1.5\n");
+        assert_eq!(
+            run("memory", &mut runner),
+            "65536 bytes (1 pages)\nlast frame: 2x1 at 0x100, 8 bytes\n"
+        );
+        assert_eq!(
+            run("dump 0x10 4", &mut runner),
+            "00000010  68 69 00 00                                      |hi..|\n"
+        );
+        assert_eq!(
+            run("rgba 256 2", &mut runner),
+            "00000100  #ff0000ff #00000000\n"
+        );
+        assert_eq!(run("f32 20 1", &mut runner), "00000014  1.5\n");
+        assert_eq!(
+            run("dump 65530 16", &mut runner),
+            "error: 16 bytes at 0xfffa are outside guest memory\n"
+        );
+        assert_eq!(
+            run("dump 1", &mut runner),
+            "unknown command \"dump 1\"; try `help`\n"
+        );
+
+        let path = std::env::temp_dir().join(format!("demo debugger {}.raw", std::process::id()));
+        run(&format!("save {}", path.display()), &mut runner);
+        assert_eq!(
+            std::fs::read(&path).unwrap(),
+            [0xff, 0, 0, 0xff, 0, 0, 0, 0]
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
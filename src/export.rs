@@ -0,0 +1,107 @@
+use crate::abi;
+use crate::plugin::Rect;
+use base64::Engine;
+use std::collections::BTreeMap;
+
+/// The page `html` fills in, with `{{name}}` placeholders.
+const TEMPLATE: &str = include_str!("../web/export.html");
+
+/// The web player, the same one `web/demo.html` loads.
+const PLAYER: &str = include_str!("../web/demo.js");
+
+/// A single HTML page that plays the module in `bytes` in a browser, even
+/// opened straight from disk. The module is checked against the guest ABI
+/// first, since the web player follows the same one. Its `title` and
+/// `author` metadata, or `name`, label the page, and the guest is offered
+/// `size` and has `params` set like on the native host.
+pub fn html(
+    bytes: &[u8],
+    name: &str,
+    size: &Rect,
+    params: &BTreeMap<String, f64>,
+) -> anyhow::Result<String> {
+    // Browsers only take the binary format.
+    let bytes = wat::parse_bytes(bytes)?;
+    let inspection = abi::inspect(&bytes)?;
+    anyhow::ensure!(
+        inspection.problems.is_empty(),
+        "the module doesn't follow the guest ABI: {}",
+        inspection.problems.join("; ")
+    );
+    let metadata = &inspection.metadata;
+    let title = metadata.get("title").map_or(name, String::as_str);
+    let caption = match metadata.get("author") {
+        Some(author) => format!("{title} by {author}"),
+        None => title.to_string(),
+    };
+    // Escaping `<` keeps the JSON from ending the script element it is in.
+    let script = |json: String| json.replace('<', "\\u003c");
+    let mut page = String::new();
+    let mut rest = TEMPLATE;
+    while let Some(start) = rest.find("{{") {
+        let end = start + rest[start..].find("}}").expect("unclosed placeholder");
+        page.push_str(&rest[..start]);
+        match &rest[start + 2..end] {
+            "title" => page.push_str(&escape(title)),
+            "caption" => page.push_str(&escape(&caption)),
+            "metadata" => page.push_str(&script(serde_json::to_string(metadata)?)),
+            "width" => page.push_str(&size.width.to_string()),
+            "height" => page.push_str(&size.height.to_string()),
+            "params" => page.push_str(&escape(&serde_json::to_string(params)?)),
+            "module" => base64::engine::general_purpose::STANDARD.encode_string(&bytes, &mut page),
+            "script" => page.push_str(PLAYER),
+            other => unreachable!("unknown placeholder {other:?}"),
+        }
+        rest = &rest[end + 2..];
+    }
+    page.push_str(rest);
+    Ok(page)
+}
+
+/// `text` as HTML text or a quoted attribute value.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_html() {
+        let wat = r#"(module
+            (memory (export "memory") 1)
+            (@custom "demo" "title = Cubes & <spheres>\nauthor = Ada\n")
+            (func (export "render") (param f64 i32 i32) (result i32) i32.const 0))"#;
+        let size = Rect {
+            width: 800,
+            height: 600,
+        };
+        let params = BTreeMap::from([("hue_speed".to_string(), 20.0)]);
+        let page = html(wat.as_bytes(), "cubes", &size, &params).unwrap();
+        assert!(page.contains("<title>Cubes &amp; &lt;spheres&gt;</title>"));
+        assert!(page.contains("<p>Cubes &amp; &lt;spheres&gt; by Ada</p>"));
+        assert!(page.contains(r#"width="800" height="600" params="{&quot;hue_speed&quot;:20.0}""#));
+        assert!(page.contains(r#""title":"Cubes & \u003cspheres>""#));
+        let module = wat::parse_str(wat).unwrap();
+        let encoded = base64::engine::general_purpose::STANDARD.encode(module);
+        assert!(page.contains(&format!("src=\"data:application/wasm;base64,{encoded}\"")));
+        assert!(page.contains("customElements.define('demo-viewer', Demo);"));
+        assert!(!page.contains("{{"));
+        // Only the page's own script elements end.
+        assert_eq!(page.matches("</script>").count(), 3);
+
+        let error = html(b"(module)", "empty", &size, &params).unwrap_err();
+        assert!(error.to_string().contains("doesn't export `render`"));
+    }
+}
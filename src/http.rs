@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+use std::io::{BufRead, Read};
+use std::net::TcpStream;
+use std::time::Duration;
+
+/// How long a client may leave the server waiting for the rest of a request,
+/// or to take the response.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The most the request line and headers may be together.
+const MAX_HEAD: u64 = 16 * 1024;
+
+const MAX_HEADERS: usize = 64;
+
+/// The request line and headers of an HTTP request. Header names are in
+/// lower case.
+#[derive(Debug)]
+pub struct Head {
+    pub method: String,
+    pub path: String,
+    pub headers: HashMap<String, String>,
+}
+
+/// Stops clients that stall from holding on to a server thread forever.
+pub fn set_timeouts(stream: &TcpStream) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))
+}
+
+/// Reads the head of a request, up to `MAX_HEAD` bytes and `MAX_HEADERS`
+/// headers, and leaves `reader` at the start of the body.
+pub fn read_head(reader: &mut impl BufRead) -> anyhow::Result<Head> {
+    let mut reader = reader.by_ref().take(MAX_HEAD);
+    let mut line = String::new();
+    let mut read_line = |line: &mut String| -> anyhow::Result<()> {
+        line.clear();
+        reader.read_line(line)?;
+        anyhow::ensure!(
+            line.ends_with('\n'),
+            "the request head is cut off or longer than {MAX_HEAD} bytes"
+        );
+        Ok(())
+    };
+    read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+
+    let mut headers = HashMap::new();
+    for count in 0.. {
+        read_line(&mut line)?;
+        if line.trim().is_empty() {
+            break;
+        }
+        anyhow::ensure!(
+            count < MAX_HEADERS,
+            "the request has more than {MAX_HEADERS} headers"
+        );
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+        }
+    }
+    Ok(Head {
+        method,
+        path,
+        headers,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read_head() {
+        let mut request = &b"PUT /params/hue HTTP/1.1\r\nContent-Length: 4\r\n\r\n0.25"[..];
+        let head = read_head(&mut request).unwrap();
+        assert_eq!(
+            (head.method.as_str(), head.path.as_str()),
+            ("PUT", "/params/hue")
+        );
+        assert_eq!(head.headers["content-length"], "4");
+        assert_eq!(request, b"0.25");
+
+        let endless = "GET /".to_string() + &"a".repeat(MAX_HEAD as usize);
+        let error = read_head(&mut endless.as_bytes()).unwrap_err();
+        assert!(error.to_string().contains("longer than"));
+        let many = "GET / HTTP/1.1\r\n".to_string() + &"A: b\r\n".repeat(MAX_HEADERS + 1);
+        let error = read_head(&mut many.as_bytes()).unwrap_err();
+        assert!(error.to_string().contains("more than"));
+        assert!(read_head(&mut &b"GET / HTTP/1.1\r\nHost: x\r\n"[..]).is_err());
+    }
+}
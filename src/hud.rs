@@ -0,0 +1,249 @@
+use crate::canvas::Canvas;
+use crate::font;
+use crate::plugin::{DemoRunner, Param};
+use crate::postprocess::Image;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How many frames the graph shows.
+const SAMPLES: usize = 120;
+
+/// How many lines of the log are shown.
+const LOG_LINES: usize = 5;
+
+/// The width of the panel in characters, and of the graph at two pixels per
+/// frame.
+const COLUMNS: usize = SAMPLES * 2 / font::WIDTH;
+
+/// The frame time at the top of the graph.
+const GRAPH_MAX: Duration = Duration::from_millis(50);
+
+const GRAPH_HEIGHT: i32 = 40;
+
+const TEXT: [u8; 4] = [255, 255, 255, 255];
+const GUEST: [u8; 4] = [80, 220, 120, 255];
+const PRESENT: [u8; 4] = [80, 160, 255, 255];
+
+struct Sample {
+    guest: Duration,
+    present: Duration,
+    at: Instant,
+}
+
+/// An overlay with the frame rate, a graph of how long frames took in the
+/// guest and in presenting them, the demo time, its parameters and the last
+/// lines of its log. Presenters draw it onto finished frames in software, so
+/// it looks the same in each of them.
+pub struct Hud {
+    visible: bool,
+    samples: VecDeque<Sample>,
+    /// The frame time marked on the graph.
+    target: Option<Duration>,
+    logs: Arc<Mutex<VecDeque<String>>>,
+}
+
+impl Hud {
+    pub fn new(visible: bool) -> Hud {
+        Hud {
+            visible,
+            samples: VecDeque::with_capacity(SAMPLES),
+            target: None,
+            logs: Default::default(),
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    /// Marks the time frames should take, e.g. a pacer's period, on the
+    /// graph.
+    pub fn set_target(&mut self, target: Duration) {
+        self.target = Some(target).filter(|target| !target.is_zero());
+    }
+
+    /// A handler for `DemoRunner::set_log_handler` that keeps the last lines
+    /// for the overlay.
+    pub fn log_handler(&self) -> impl Fn(&str) + Send + Sync + 'static {
+        let logs = self.logs.clone();
+        move |message| {
+            let mut logs = logs.lock().unwrap();
+            logs.extend(message.lines().map(str::to_string));
+            let excess = logs.len().saturating_sub(LOG_LINES);
+            logs.drain(..excess);
+        }
+    }
+
+    /// Records that a frame took `guest` to render and `present` to show.
+    pub fn record(&mut self, guest: Duration, present: Duration) {
+        self.record_at(Instant::now(), guest, present);
+    }
+
+    fn record_at(&mut self, at: Instant, guest: Duration, present: Duration) {
+        if self.samples.len() == SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(Sample { guest, present, at });
+    }
+
+    /// Frames per second over the frames in the graph.
+    pub fn fps(&self) -> Option<f64> {
+        let (first, last) = (self.samples.front()?, self.samples.back()?);
+        let elapsed = last.at.duration_since(first.at).as_secs_f64();
+        (elapsed > 0.0).then(|| (self.samples.len() - 1) as f64 / elapsed)
+    }
+
+    /// Like `draw`, with the parameters of the demo `runner` runs.
+    pub fn draw_demo(&self, image: &mut Image, time: f64, runner: &mut DemoRunner) {
+        if self.visible {
+            // A guest that fails to list them still gets the rest.
+            let params = runner.params().unwrap_or_default();
+            self.draw(image, time, &params);
+        }
+    }
+
+    /// Draws the overlay in the top-left corner of `image`, if it is
+    /// visible.
+    pub fn draw(&self, image: &mut Image, time: f64, params: &[Param]) {
+        if !self.visible {
+            return;
+        }
+        let scale = if image.size.width >= 1280 { 2 } else { 1 };
+        let margin = 4 * scale;
+        let line = font::HEIGHT as i32 * scale;
+        let graph_height = GRAPH_HEIGHT * scale;
+        let logs = self.logs.lock().unwrap();
+
+        let mut lines = vec![format!("time {time:.3} s")];
+        lines.extend(
+            params
+                .iter()
+                .map(|param| format!("{} = {:.3}", param.name, param.value)),
+        );
+        lines.extend(logs.iter().cloned());
+        let width = COLUMNS as i32 * font::WIDTH as i32 * scale;
+        let height = 2 * line + graph_height + lines.len() as i32 * line;
+
+        let mut canvas = Canvas::new(&mut image.data, &image.size);
+        canvas.blend_rect(
+            0,
+            0,
+            width + 2 * margin,
+            height + 2 * margin,
+            [0, 0, 0, 176],
+        );
+        let mut y = margin;
+        let average = self
+            .samples
+            .iter()
+            .map(|sample| sample.guest + sample.present)
+            .sum::<Duration>()
+            .checked_div(self.samples.len() as u32);
+        let stats = match (self.fps(), average) {
+            (Some(fps), Some(average)) => {
+                format!("{fps:.1} fps  {:.1} ms", average.as_secs_f64() * 1e3)
+            }
+            _ => "-- fps".to_string(),
+        };
+        canvas.draw_text(margin, y, &stats, scale, TEXT);
+        y += line;
+
+        // Newest on the right, with the guest's time below the presenter's.
+        let bar = |duration: Duration| {
+            (duration.as_secs_f64() / GRAPH_MAX.as_secs_f64() * graph_height as f64).round() as i32
+        };
+        let bottom = y + graph_height;
+        let first = SAMPLES - self.samples.len();
+        for (i, sample) in self.samples.iter().enumerate() {
+            let x = margin + ((first + i) * 2) as i32 * scale;
+            let guest = bar(sample.guest).min(graph_height);
+            let present = bar(sample.present).min(graph_height - guest);
+            canvas.fill_rect(x, bottom - guest, 2 * scale, guest, GUEST);
+            canvas.fill_rect(x, bottom - guest - present, 2 * scale, present, PRESENT);
+        }
+        if let Some(target) = self.target {
+            let target = bar(target).min(graph_height);
+            canvas.blend_rect(margin, bottom - target, width, scale, [255, 255, 255, 96]);
+        }
+        y = bottom;
+        let x = canvas.draw_text(margin, y, "guest ", scale, GUEST);
+        canvas.draw_text(x, y, "present", scale, PRESENT);
+        y += line;
+
+        for text in lines {
+            let text: String = text.chars().take(COLUMNS).collect();
+            canvas.draw_text(margin, y, &text, scale, TEXT);
+            y += line;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::plugin::Rect;
+
+    #[test]
+    fn test_hud() {
+        let mut hud = Hud::new(false);
+        let start = Instant::now();
+        for i in 0..=SAMPLES as u64 {
+            hud.record_at(
+                start + Duration::from_millis(20 * i),
+                Duration::from_millis(8),
+                Duration::from_millis(4),
+            );
+        }
+        assert_eq!(hud.samples.len(), SAMPLES);
+        assert_eq!(hud.fps().unwrap().round(), 50.0);
+        let log = hud.log_handler();
+        for i in 0..10 {
+            log(&format!("line {i}"));
+        }
+        assert_eq!(hud.logs.lock().unwrap().front().unwrap(), "line 5");
+
+        let size = Rect {
+            width: 400,
+            height: 300,
+        };
+        let blank = Image {
+            data: vec![255; 400 * 300 * 4],
+            size,
+        };
+        let mut image = blank.clone();
+        hud.draw(&mut image, 1.0, &[]);
+        assert_eq!(image, blank);
+
+        hud.toggle();
+        let params = [Param {
+            name: "hue".to_string(),
+            value: 0.5,
+        }];
+        hud.draw(&mut image, 1.0, &params);
+        let pixel = |image: &Image, x: usize, y: usize| {
+            let start = (y * 400 + x) * 4;
+            image.data[start..start + 4].to_vec()
+        };
+        // The panel darkens what is under it and leaves the rest alone.
+        assert_eq!(pixel(&image, 1, 1), [79, 79, 79, 255]);
+        assert_eq!(pixel(&image, 399, 299), [255; 4]);
+        // The newest frame's bar, at the right end of the graph.
+        let x = 4 + (SAMPLES - 1) * 2;
+        let bottom = 4 + font::HEIGHT + GRAPH_HEIGHT as usize;
+        assert_eq!(pixel(&image, x, bottom - 1), GUEST);
+        assert_eq!(pixel(&image, x, bottom - 8), PRESENT);
+        // The target frame time is marked across the graph when there is one.
+        let target = bottom - 16;
+        assert_eq!(pixel(&image, x, target), [79, 79, 79, 255]);
+        hud.set_target(Duration::from_millis(20));
+        let mut marked = blank.clone();
+        hud.draw(&mut marked, 1.0, &params);
+        assert_ne!(pixel(&marked, x, target), pixel(&image, x, target));
+        assert_eq!(pixel(&marked, x, target - 1), pixel(&image, x, target - 1));
+    }
+}
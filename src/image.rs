@@ -0,0 +1,59 @@
+use crate::plugin::Rect;
+use std::io::Write;
+
+/// Writes `size.width * size.height` RGBA pixels as a PNG image.
+pub fn write_png(out: impl Write, data: &[u8], size: &Rect) -> anyhow::Result<()> {
+    write_png_with_text(out, data, size, &[])
+}
+
+/// Like `write_png`, also storing `text` as `keyword, text` pairs. They go in
+/// `tEXt` chunks, or `iTXt` chunks if they aren't Latin-1.
+pub fn write_png_with_text(
+    out: impl Write,
+    data: &[u8],
+    size: &Rect,
+    text: &[(String, String)],
+) -> anyhow::Result<()> {
+    let mut encoder = png::Encoder::new(out, size.width as u32, size.height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    for (keyword, text) in text {
+        if text.chars().all(|c| c <= '\u{ff}') {
+            encoder.add_text_chunk(keyword.clone(), text.clone())?;
+        } else {
+            encoder.add_itxt_chunk(keyword.clone(), text.clone())?;
+        }
+    }
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(data)?;
+    Ok(writer.finish()?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_write_png() {
+        let size = Rect {
+            width: 2,
+            height: 1,
+        };
+        let data = [255, 0, 0, 255, 0, 0, 255, 128];
+        let text = [
+            ("Title".to_string(), "Café".to_string()),
+            ("demo:time".to_string(), "1.5 → 2".to_string()),
+        ];
+        let mut png = Vec::new();
+        write_png_with_text(&mut png, &data, &size, &text).unwrap();
+
+        let mut reader = png::Decoder::new(png.as_slice()).read_info().unwrap();
+        let info = reader.info();
+        assert_eq!((info.width, info.height), (2, 1));
+        assert_eq!(info.uncompressed_latin1_text[0].text, "Café");
+        assert_eq!(info.utf8_text[0].get_text().unwrap(), "1.5 → 2");
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut pixels).unwrap();
+        assert_eq!(pixels, data);
+    }
+}
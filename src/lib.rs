@@ -0,0 +1,67 @@
+//! Runs demos: WebAssembly modules that render frames into their own memory.
+//!
+//! A [`DemoRunner`] loads one module and calls into it. The host asks the
+//! guest to pick a size and render frames, passes it input and parameter
+//! changes, and collects its log output:
+//!
+//! ```
+//! # fn main() -> anyhow::Result<()> {
+//! # let path = std::env::temp_dir().join("demo-doc-crate.wat");
+//! # std::fs::write(&path, r#"(module
+//! #     (memory (export "memory") 20)
+//! #     (func (export "render") (param f64 i32 i32) (result i32) i32.const 0))"#)?;
+//! use demo::{Config, Options};
+//!
+//! let mut runner = demo::create_file_with_options(&path, &Options::default())?;
+//! runner.set_log_handler(|line| eprintln!("guest: {line}"));
+//! let settings = Config::default().settings("cube", "");
+//! let size = runner.call_set_dimensions(
+//!     demo::BASE_DPI,
+//!     &settings.min_size,
+//!     &settings.size,
+//!     &settings.max_size,
+//! )?;
+//! let frame = runner.render_frame(0.0, &size)?;
+//! assert_eq!(frame.data.len(), 640 * 480 * 4);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The guest ABI is described in [`abi`] and on the methods of
+//! [`DemoRunner`] that call each export. [`Supervisor`] keeps a demo running
+//! when its guest traps, and the other modules hold what the `demo` command
+//! line tool is built from.
+
+mod canvas;
+mod font;
+mod http;
+
+pub mod abi;
+pub mod catalogue;
+pub mod config;
+pub mod debugger;
+pub mod export;
+pub mod hud;
+pub mod image;
+pub mod pacing;
+pub mod playback;
+pub mod plugin;
+pub mod postprocess;
+pub mod profile;
+pub mod remote;
+pub mod replay;
+pub mod scale;
+pub mod screenshot;
+pub mod stream;
+pub mod supervisor;
+#[cfg(windows)]
+pub mod window;
+
+pub use config::{Config, Settings};
+pub use playback::Playback;
+pub use plugin::{
+    create_bytes, create_bytes_with_options, create_file, create_file_with_options, create_wat,
+    create_wat_with_options, DemoRunner, DemoRuntime, FrameView, Input, LogHandler, Options, Param,
+    PixelFormat, Profiler, Rect, Region, Resolution, RuntimeOptions, BASE_DPI,
+};
+pub use supervisor::{RestartLimit, Supervisor};
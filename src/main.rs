@@ -1,537 +1,570 @@
-use std::ffi::c_void;
-use windows::{
-    core::*, Foundation::Numerics::*, Win32::Foundation::*, Win32::Graphics::Direct2D::Common::*,
-    Win32::Graphics::Direct2D::*, Win32::Graphics::Direct3D::*, Win32::Graphics::Direct3D11::*,
-    Win32::Graphics::Dxgi::Common::*, Win32::Graphics::Dxgi::*, Win32::Graphics::Gdi::*,
-    Win32::System::Com::*, Win32::System::LibraryLoader::*, Win32::System::Performance::*,
-    Win32::System::SystemInformation::GetLocalTime, Win32::UI::Animation::*,
-    Win32::UI::WindowsAndMessaging::*,
-};
-mod plugin;
-use plugin::DemoRunner;
-
-fn main() {
-    match do_main() {
-        Ok(_) => {}
+mod cli;
+
+use anyhow::Context;
+use clap::Parser;
+use cli::{Cli, Command, DemoArgs, HeadlessArgs};
+use demo::config::Manifest;
+use demo::hud::Hud;
+use demo::postprocess::Image;
+use demo::remote::Remote;
+use demo::replay::{self, Replay};
+use demo::supervisor::{RestartLimit, Supervisor};
+use demo::{abi, catalogue, debugger, export, image, plugin, screenshot, stream};
+use demo::{Config, DemoRunner, Playback, Settings};
+use std::io::BufRead;
+use std::path::Path;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+/// Exit status when a module doesn't pass `validate`. Usage errors exit with
+/// 2 and any other error with 1.
+const EXIT_INVALID: u8 = 3;
+
+/// Exit status when `replay` renders frames that differ from the recording.
+const EXIT_DIVERGED: u8 = 4;
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match do_main(cli) {
+        Ok(code) => code,
         Err(e) => {
-            eprintln!("{}", e);
-            std::process::exit(1);
+            // The debug format includes the cause chain and, for traps, the
+            // wasm backtrace.
+            eprintln!("error: {:?}", e);
+            ExitCode::FAILURE
         }
     }
 }
-fn do_main() -> anyhow::Result<()> {
-    unsafe {
-        CoInitializeEx(None, COINIT_MULTITHREADED)?;
-    }
-    let runner = plugin::create_file("./sdf/target/wasm32-unknown-unknown/release/sdf.wasm")?;
-    let mut window = Window::new(runner)?;
 
-    Ok(window.run()?)
+fn do_main(cli: Cli) -> anyhow::Result<ExitCode> {
+    match cli.command {
+        Command::Run(args) => run(args),
+        Command::Render(args) => render(args),
+        Command::Screenshot(args) => screenshot(args),
+        Command::Debug(args) => debug(args),
+        Command::Replay(args) => replay(args),
+        Command::Stream(args) => stream(args),
+        Command::Bench(args) => bench(args),
+        Command::Inspect(args) => inspect(args),
+        Command::Validate(args) => validate(args),
+        Command::List(args) => list(args),
+        Command::Export(args) => export(args),
+    }
 }
 
-struct Window {
-    handle: HWND,
-    factory: ID2D1Factory1,
-    dxfactory: IDXGIFactory2,
-    manager: IUIAnimationManager,
-    variable: IUIAnimationVariable,
-
-    target: Option<ID2D1DeviceContext>,
-    swapchain: Option<IDXGISwapChain1>,
-    brush: Option<ID2D1SolidColorBrush>,
-    clock: Option<ID2D1Bitmap1>,
-    dpi: f32,
-    visible: bool,
-    occlusion: u32,
-    frequency: i64,
-
-    demo_runner: DemoRunner,
+/// The settings the config file has for the module at `path`.
+fn config_settings(path: &Path, bytes: &[u8], config: Option<&Path>) -> anyhow::Result<Settings> {
+    let config = Config::load(config)?;
+    Ok(config.settings(&catalogue::module_name(path), &catalogue::sha1_hex(bytes)))
 }
 
-impl Window {
-    fn new(demo_runner: DemoRunner) -> anyhow::Result<Self> {
-        let factory = create_factory()?;
-        let dxfactory: IDXGIFactory2 = unsafe { CreateDXGIFactory1()? };
-        let manager: IUIAnimationManager =
-            unsafe { CoCreateInstance(&UIAnimationManager, None, CLSCTX_ALL)? };
-        let transition = create_transition()?;
-
-        let mut dpi = 0.0;
-        let mut dpiy = 0.0;
-        unsafe { factory.GetDesktopDpi(&mut dpi, &mut dpiy) };
-
-        let mut frequency = 0;
-        unsafe { QueryPerformanceFrequency(&mut frequency)? };
-
-        let variable = unsafe {
-            let variable = manager.CreateAnimationVariable(0.0)?;
-
-            manager.ScheduleTransition(&variable, &transition, get_time(frequency)?)?;
+/// The capabilities a module may use: what its config settings allow, cut
+/// down to what the `demo.toml` next to it asks for.
+fn module_policy(path: &Path, settings: &Settings) -> anyhow::Result<plugin::Policy> {
+    let manifest = Manifest::for_module(path)?;
+    Ok(settings.policy.intersect(&manifest.policy()))
+}
 
-            variable
-        };
+/// Loads the demo with its settings from the config file, overridden by
+/// the command line.
+fn load(demo: &DemoArgs, options: &plugin::Options) -> anyhow::Result<(DemoRunner, Settings)> {
+    load_with_runtime(demo, &demo.runtime_options(), options)
+}
 
-        Ok(Window {
-            handle: HWND(0),
-            factory,
-            dxfactory,
-            manager,
-            variable,
-            target: None,
-            swapchain: None,
-            brush: None,
-            clock: None,
-            dpi,
-            visible: false,
-            occlusion: 0,
-            frequency,
-            demo_runner,
-        })
+fn load_with_runtime(
+    demo: &DemoArgs,
+    runtime: &plugin::RuntimeOptions,
+    options: &plugin::Options,
+) -> anyhow::Result<(DemoRunner, Settings)> {
+    let bytes = std::fs::read(&demo.module)
+        .with_context(|| format!("reading {}", demo.module.display()))?;
+    let mut settings = config_settings(&demo.module, &bytes, demo.config.as_deref())?;
+    if let Some(width) = demo.width {
+        settings.size.width = width;
     }
-
-    fn render(&mut self) -> anyhow::Result<()> {
-        if self.target.is_none() {
-            let device = create_device()?;
-            let target = create_render_target(&self.factory, &device)?;
-            unsafe { target.SetDpi(self.dpi, self.dpi) };
-
-            let swapchain = create_swapchain(&device, self.handle)?;
-            create_swapchain_bitmap(&swapchain, &target)?;
-
-            self.brush = create_brush(&target).ok();
-            self.target = Some(target);
-            self.swapchain = Some(swapchain);
-            self.create_device_size_resources()?;
-        }
-
-        let taken_target = self.target.take(); // make borrow checker happy
-        let target = taken_target.as_ref().unwrap();
-        unsafe { target.BeginDraw() };
-        self.draw(target)?;
-
-        unsafe {
-            target.EndDraw(None, None)?;
-        }
-        self.target = taken_target; // put it back
-
-        if let Err(error) = self.present(1, 0) {
-            if error.code() == DXGI_STATUS_OCCLUDED {
-                self.occlusion = unsafe {
-                    self.dxfactory
-                        .RegisterOcclusionStatusWindow(self.handle, WM_USER)?
-                };
-                self.visible = false;
-            } else {
-                self.release_device();
-            }
-        }
-
-        Ok(())
+    if let Some(height) = demo.height {
+        settings.size.height = height;
     }
-
-    fn release_device(&mut self) {
-        self.target = None;
-        self.swapchain = None;
-        self.release_device_resources();
+    settings.params.extend(demo.params.iter().cloned());
+    if let Some(scale) = demo.render_scale {
+        settings.render_scale = scale;
     }
-
-    fn release_device_resources(&mut self) {
-        self.brush = None;
-        self.clock = None;
+    if let Some(filter) = demo.scale_filter {
+        settings.scale_filter = filter.into();
     }
-
-    fn present(&self, sync: u32, flags: u32) -> Result<()> {
-        unsafe { Ok(self.swapchain.as_ref().unwrap().Present(sync, flags).ok()?) }
+    settings.hud |= demo.hud;
+    settings.policy = module_policy(&demo.module, &settings)?;
+
+    let mut options = options.clone();
+    options.policy = settings.policy.clone();
+    let mut runner = plugin::DemoRuntime::shared(runtime)?
+        .create_file(&demo.module, &options)
+        .with_context(|| format!("loading {}", demo.module.display()))?;
+    for (name, value) in &settings.params {
+        runner.set_param(name, *value)?;
     }
+    Ok((runner, settings))
+}
 
-    fn draw(&mut self, target: &ID2D1DeviceContext) -> anyhow::Result<()> {
-        let clock = self.clock.as_ref().unwrap();
-
-        unsafe {
-            let now = get_time(self.frequency)?;
-            self.manager.Update(now, None)?;
-
-            target.Clear(Some(&D2D1_COLOR_F {
-                r: 1.0,
-                g: 1.0,
-                b: 1.0,
-                a: 1.0,
-            }));
-
-            let px_size = clock.GetPixelSize();
-            self.demo_runner.call_render(
-                now,
-                &plugin::Rect {
-                    width: px_size.width as i32,
-                    height: px_size.height as i32,
-                },
-                |data: &[u8]| {
-                    let data_ptr: *const u8 = data.as_ptr();
-                    clock.CopyFromMemory(None, data_ptr as *const c_void, px_size.width * 4)?;
-                    Ok(())
-                },
-            )?;
-
-            let _var_val = self.variable.GetValue()?;
-            target.DrawBitmap(
-                clock,
-                None,
-                1.0,
-                D2D1_BITMAP_INTERPOLATION_MODE_LINEAR,
-                None,
-            );
-        }
-
-        Ok(())
+/// The HUD overlay, collecting the demo's log if it is shown.
+fn hud(runner: &mut DemoRunner, settings: &Settings) -> Hud {
+    let mut hud = Hud::new(settings.hud);
+    hud.set_target(settings.target_frame_time);
+    if settings.hud {
+        runner.set_log_handler(hud.log_handler());
     }
+    hud
+}
 
-    fn create_device_size_resources(&mut self) -> Result<()> {
-        let target = self.target.as_ref().unwrap();
-        let clock = self.create_clock(target)?;
-        self.clock = Some(clock);
+/// Sets the demo up for rendering without a display and returns the frame
+/// size, and the largest frame size the guest was told to expect.
+fn set_up_headless(
+    runner: &mut DemoRunner,
+    settings: &Settings,
+    args: &HeadlessArgs,
+) -> anyhow::Result<(plugin::Rect, plugin::Rect)> {
+    let resolution = runner.call_set_dpi(args.dpi)?;
+    let logical = runner.call_set_dimensions(
+        args.dpi,
+        &settings.min_size,
+        &settings.size,
+        &settings.max_size,
+    )?;
+    Ok((
+        resolution.render_size(&logical, args.dpi),
+        resolution.render_size(&settings.max_size, args.dpi),
+    ))
+}
 
-        Ok(())
+fn run(args: cli::RunArgs) -> anyhow::Result<ExitCode> {
+    let mut runtime = args.demo.runtime_options();
+    runtime.debug_info |= cfg!(debug_assertions);
+    runtime.canonicalize_nans = args.record.is_some();
+    let (mut runner, mut settings) =
+        load_with_runtime(&args.demo, &runtime, &plugin::Options::default())?;
+    if let Some(speed) = args.speed {
+        settings.speed = speed;
     }
-
-    fn create_clock(&self, target: &ID2D1DeviceContext) -> Result<ID2D1Bitmap1> {
-        let size_f = unsafe { target.GetSize() };
-
-        let size_u = D2D_SIZE_U {
-            width: (size_f.width * self.dpi / 96.0) as u32,
-            height: (size_f.height * self.dpi / 96.0) as u32,
-        };
-
-        let properties = D2D1_BITMAP_PROPERTIES1 {
-            pixelFormat: D2D1_PIXEL_FORMAT {
-                format: DXGI_FORMAT_B8G8R8A8_UNORM,
-                alphaMode: D2D1_ALPHA_MODE_PREMULTIPLIED,
-            },
-            dpiX: self.dpi,
-            dpiY: self.dpi,
-            bitmapOptions: D2D1_BITMAP_OPTIONS_TARGET,
-            ..Default::default()
-        };
-
-        unsafe { target.CreateBitmap2(size_u, None, 0, &properties) }
+    if let Some(vsync) = args.vsync {
+        settings.vsync = vsync;
     }
-
-    fn resize_swapchain_bitmap(&mut self) -> anyhow::Result<()> {
-        if let Some(target) = &self.target {
-            let swapchain = self.swapchain.as_ref().unwrap();
-            unsafe { target.SetTarget(None) };
-
-            if unsafe {
-                swapchain
-                    .ResizeBuffers(0, 0, 0, DXGI_FORMAT_UNKNOWN, 0)
-                    .is_ok()
-            } {
-                create_swapchain_bitmap(swapchain, target)?;
-                self.create_device_size_resources()?;
-            } else {
-                self.release_device();
-            }
-
-            self.render()?;
-        }
-
-        Ok(())
+    if let Some(fps) = args.max_fps {
+        settings.max_fps = fps;
     }
-
-    fn message_handler(&mut self, message: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
-        unsafe {
-            match message {
-                WM_PAINT => {
-                    let mut ps = PAINTSTRUCT::default();
-                    BeginPaint(self.handle, &mut ps);
-                    self.render().unwrap();
-                    EndPaint(self.handle, &ps);
-                    LRESULT(0)
-                }
-                WM_SIZE => {
-                    if wparam.0 != SIZE_MINIMIZED as usize {
-                        self.resize_swapchain_bitmap().unwrap();
-                    }
-                    LRESULT(0)
-                }
-                WM_DISPLAYCHANGE => {
-                    self.render().unwrap();
-                    LRESULT(0)
-                }
-                WM_USER => {
-                    if self.present(0, DXGI_PRESENT_TEST).is_ok() {
-                        self.dxfactory.UnregisterOcclusionStatus(self.occlusion);
-                        self.occlusion = 0;
-                        self.visible = true;
-                    }
-                    LRESULT(0)
-                }
-                WM_ACTIVATE => {
-                    self.visible = true; // TODO: unpack !HIWORD(wparam);
-                    LRESULT(0)
-                }
-                WM_DESTROY => {
-                    PostQuitMessage(0);
-                    LRESULT(0)
-                }
-                _ => DefWindowProcA(self.handle, message, wparam, lparam),
-            }
+    if let Some(wait) = args.frame_wait {
+        settings.frame_wait = wait.into();
+    }
+    if let Some(skip) = args.frame_skip {
+        settings.frame_skip = skip;
+    }
+    // The HUD can be toggled on at any time, so it always keeps the log.
+    let hud = Hud::new(settings.hud);
+    let hud_log = hud.log_handler();
+    let remote = match args.remote {
+        Some(addr) => {
+            let remote = Remote::start(addr)?;
+            let remote_log = remote.log_handler();
+            runner.set_log_handler(move |message| {
+                remote_log(message);
+                hud_log(message);
+            });
+            println!("remote control on http://{}", remote.addr());
+            Some(remote)
+        }
+        None => {
+            runner.set_log_handler(hud_log);
+            None
         }
+    };
+    let mut playback = Playback::default();
+    playback.set_speed(settings.speed);
+    playback.set_frame_locked(!settings.frame_skip);
+    playback.seek(args.start);
+    if args.record.is_some() {
+        runner.start_recording();
     }
+    present(runner, &args, settings, playback, remote, hud)?;
+    Ok(ExitCode::SUCCESS)
+}
 
-    fn run(&mut self) -> anyhow::Result<()> {
-        unsafe {
-            let instance = GetModuleHandleA(None)?;
-            debug_assert!(instance.0 != 0);
-            let window_class = s!("window");
-
-            let wc = WNDCLASSA {
-                hCursor: LoadCursorW(None, IDC_HAND)?,
-                hInstance: instance.into(),
-                lpszClassName: window_class,
-
-                style: CS_HREDRAW | CS_VREDRAW,
-                lpfnWndProc: Some(Self::wndproc),
-                ..Default::default()
-            };
-
-            let atom = RegisterClassA(&wc);
-            debug_assert!(atom != 0);
-
-            let plugin::Rect { width, height } = self.demo_runner.call_set_dimensions(
-                32,
-                &plugin::Rect {
-                    width: 100,
-                    height: 100,
-                },
-                &plugin::Rect {
-                    width: 640,
-                    height: 480,
-                },
-                &plugin::Rect {
-                    width: 2560,
-                    height: 1440,
-                },
-            )?;
-            println!("demo selected {}, {}", width, height);
-            let mut r = RECT {
-                left: 0,
-                right: width,
-                top: 0,
-                bottom: height,
-            };
-            AdjustWindowRect(&mut r as *mut RECT, WS_OVERLAPPEDWINDOW, false)?;
-
-            let handle = CreateWindowExA(
-                WINDOW_EX_STYLE::default(),
-                window_class,
-                s!("Sample Window"),
-                WS_OVERLAPPEDWINDOW | WS_VISIBLE,
-                CW_USEDEFAULT,
-                CW_USEDEFAULT,
-                r.right - r.left,
-                r.bottom - r.top,
-                None,
-                None,
-                instance,
-                Some(self as *mut _ as _),
-            );
-
-            debug_assert!(handle.0 != 0);
-            debug_assert!(handle == self.handle);
-            let mut message = MSG::default();
-
-            loop {
-                if self.visible {
-                    self.render()?;
-
-                    while PeekMessageA(&mut message, None, 0, 0, PM_REMOVE).into() {
-                        if message.message == WM_QUIT {
-                            return Ok(());
-                        }
-                        DispatchMessageA(&message);
-                    }
-                } else {
-                    GetMessageA(&mut message, None, 0, 0);
+#[cfg(windows)]
+fn present(
+    runner: DemoRunner,
+    args: &cli::RunArgs,
+    settings: Settings,
+    playback: Playback,
+    remote: Option<Remote>,
+    hud: Hud,
+) -> anyhow::Result<()> {
+    demo::window::run(
+        runner,
+        demo::window::Settings {
+            config: settings,
+            fullscreen: args.fullscreen,
+            playback,
+            remote,
+            console: args.console.then(debugger::Console::start),
+            hud,
+            record: args.record.clone(),
+        },
+    )
+}
 
-                    if message.message == WM_QUIT {
-                        return Ok(());
-                    }
+#[cfg(not(windows))]
+fn present(
+    _runner: DemoRunner,
+    _args: &cli::RunArgs,
+    _settings: Settings,
+    _playback: Playback,
+    _remote: Option<Remote>,
+    _hud: Hud,
+) -> anyhow::Result<()> {
+    anyhow::bail!("there is no window presenter for this platform; try `render` or `stream`")
+}
 
-                    DispatchMessageA(&message);
-                }
-            }
-        }
+fn render(args: cli::RenderArgs) -> anyhow::Result<ExitCode> {
+    let mut options = plugin::Options::default();
+    options.trace = args.trace.is_some();
+    let (mut runner, settings) = load(&args.headless.demo, &options)?;
+    let (size, _) = set_up_headless(&mut runner, &settings, &args.headless)?;
+    std::fs::create_dir_all(&args.out)
+        .with_context(|| format!("creating {}", args.out.display()))?;
+    let mut scaler = settings.scaler();
+    let mut hud = hud(&mut runner, &settings);
+    for i in 0..args.frames {
+        let time = args.start + i as f64 / args.fps;
+        let mut image = scaler.render(
+            |size| runner.render_frame(time, size),
+            &size,
+            &settings.postprocess,
+        )?;
+        hud.draw_demo(&mut image, time, &mut runner);
+        let start = Instant::now();
+        let path = args.out.join(format!("frame-{i:05}.png"));
+        let file =
+            std::fs::File::create(&path).with_context(|| format!("creating {}", path.display()))?;
+        image::write_png(std::io::BufWriter::new(file), &image.data, &image.size)?;
+        hud.record(scaler.render_time(), start.elapsed());
     }
+    println!(
+        "rendered {} frames of {}x{} to {}",
+        args.frames,
+        size.width,
+        size.height,
+        args.out.display()
+    );
+    if let Some(path) = &args.trace {
+        let file =
+            std::fs::File::create(path).with_context(|| format!("creating {}", path.display()))?;
+        runner.write_chrome_trace(std::io::BufWriter::new(file))?;
+    }
+    Ok(ExitCode::SUCCESS)
+}
 
-    extern "system" fn wndproc(
-        window: HWND,
-        message: u32,
-        wparam: WPARAM,
-        lparam: LPARAM,
-    ) -> LRESULT {
-        unsafe {
-            if message == WM_NCCREATE {
-                let cs = lparam.0 as *const CREATESTRUCTA;
-                let this = (*cs).lpCreateParams as *mut Self;
-                (*this).handle = window;
-
-                SetWindowLongPtrA(window, GWLP_USERDATA, this as _);
-            } else {
-                let this = GetWindowLongPtrA(window, GWLP_USERDATA) as *mut Self;
+fn screenshot(args: cli::ScreenshotArgs) -> anyhow::Result<ExitCode> {
+    let (mut runner, mut settings) = load(&args.headless.demo, &plugin::Options::default())?;
+    if let Some(scale) = args.scale {
+        settings.screenshot_scale = scale;
+    }
+    let (size, max) = set_up_headless(&mut runner, &settings, &args.headless)?;
+    let scaled = |length: i32| ((length as f64 * settings.screenshot_scale).round() as i32).max(1);
+    let size = plugin::Rect {
+        width: scaled(size.width),
+        height: scaled(size.height),
+    };
+    let hud = hud(&mut runner, &settings);
+    let image = screenshot::save(
+        &mut runner,
+        args.time,
+        &size,
+        &max,
+        &settings.postprocess,
+        &hud,
+        &args.out,
+    )?;
+    println!(
+        "saved {}x{} to {}",
+        image.size.width,
+        image.size.height,
+        args.out.display()
+    );
+    Ok(ExitCode::SUCCESS)
+}
 
-                if !this.is_null() {
-                    return (*this).message_handler(message, wparam, lparam);
-                }
+fn debug(args: cli::DebugArgs) -> anyhow::Result<ExitCode> {
+    let (mut runner, settings) = load(&args.headless.demo, &plugin::Options::default())?;
+    let (size, _) = set_up_headless(&mut runner, &settings, &args.headless)?;
+    let mut playback = Playback::default();
+    playback.pause();
+    playback.seek(args.start);
+    println!("type `help` for commands, and `quit` to stop");
+    let mut rendered = None;
+    let mut lines = std::io::stdin().lock().lines();
+    loop {
+        // Only render when the time moved, so looking around doesn't change
+        // the guest's state.
+        if playback.is_playing() || rendered != Some(playback.time()) {
+            rendered = Some(playback.time());
+            if let Err(error) = runner.render_frame(playback.time(), &size) {
+                println!("error: {error:#}");
             }
-
-            DefWindowProcA(window, message, wparam, lparam)
+        }
+        let Some(line) = lines.next().transpose()? else {
+            break;
+        };
+        match line.trim() {
+            "" => {}
+            "quit" => break,
+            line => print!(
+                "{}",
+                debugger::run_line(line, &mut playback, &mut runner, 1.0 / args.fps)
+            ),
         }
     }
+    Ok(ExitCode::SUCCESS)
 }
 
-fn get_time(frequency: i64) -> Result<f64> {
-    unsafe {
-        let mut time = 0;
-        QueryPerformanceCounter(&mut time)?;
-        Ok(time as f64 / frequency as f64)
+fn replay(args: cli::ReplayArgs) -> anyhow::Result<ExitCode> {
+    let replay = Replay::load(&args.replay)?;
+    let bytes = std::fs::read(&args.module)
+        .with_context(|| format!("reading {}", args.module.display()))?;
+    let hash = catalogue::sha1_hex(&bytes);
+    if hash != replay.module {
+        eprintln!(
+            "warning: {} was recorded with module {}, not {hash}; its frames may differ",
+            args.replay.display(),
+            replay.module
+        );
     }
+    let mut runtime = plugin::RuntimeOptions::default();
+    runtime.debug_info = args.debug_info;
+    runtime.canonicalize_nans = true;
+    let mut runner = plugin::DemoRuntime::shared(&runtime)?
+        .create_bytes(&bytes, &plugin::Options::default())
+        .with_context(|| format!("loading {}", args.module.display()))?;
+    if let Some(out) = &args.out {
+        std::fs::create_dir_all(out).with_context(|| format!("creating {}", out.display()))?;
+    }
+    let report = replay::play(&replay, &mut runner, |index, frame| {
+        let Some(out) = &args.out else {
+            return Ok(());
+        };
+        let image = Image::from_rows(frame.data, &frame.size, frame.stride);
+        let path = out.join(format!("frame-{index:05}.png"));
+        let file =
+            std::fs::File::create(&path).with_context(|| format!("creating {}", path.display()))?;
+        image::write_png(std::io::BufWriter::new(file), &image.data, &image.size)
+    })?;
+    for (frame, error) in &report.failures {
+        println!("frame {frame}: guest failed: {error}");
+    }
+    for frame in &report.mismatches {
+        println!("frame {frame} differs from the recording");
+    }
+    println!(
+        "replayed {} frames; {} differ from the recording",
+        report.frames,
+        report.mismatches.len()
+    );
+    Ok(if report.mismatches.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(EXIT_DIVERGED)
+    })
 }
 
-fn create_brush(target: &ID2D1DeviceContext) -> anyhow::Result<ID2D1SolidColorBrush> {
-    let color = D2D1_COLOR_F {
-        r: 0.92,
-        g: 0.38,
-        b: 0.208,
-        a: 1.0,
-    };
-
-    let properties = D2D1_BRUSH_PROPERTIES {
-        opacity: 0.8,
-        transform: Matrix3x2::identity(),
-    };
-
-    unsafe { Ok(target.CreateSolidColorBrush(&color, Some(&properties))?) }
-}
-
-fn create_factory() -> anyhow::Result<ID2D1Factory1> {
-    let mut options = D2D1_FACTORY_OPTIONS::default();
-
-    if cfg!(debug_assertions) {
-        options.debugLevel = D2D1_DEBUG_LEVEL_INFORMATION;
+fn stream(args: cli::StreamArgs) -> anyhow::Result<ExitCode> {
+    if args.http.is_none() && args.raw.is_none() {
+        anyhow::bail!("nothing to serve; pass --http, --raw or both");
     }
-
-    unsafe {
-        Ok(D2D1CreateFactory(
-            D2D1_FACTORY_TYPE_SINGLE_THREADED,
-            Some(&options),
-        )?)
+    let (mut runner, settings) = load(&args.headless.demo, &plugin::Options::default())?;
+    let (size, _) = set_up_headless(&mut runner, &settings, &args.headless)?;
+    let broadcast = stream::Broadcast::new(args.quality);
+    if let Some(addr) = args.http {
+        println!("streaming on http://{}", broadcast.listen_http(addr)?);
+    }
+    if let Some(addr) = args.raw {
+        println!("streaming raw frames on {}", broadcast.listen_raw(addr)?);
     }
+    let mut hud = hud(&mut runner, &settings);
+    stream::run(
+        &mut Supervisor::new(runner, RestartLimit::default()),
+        &size,
+        args.fps,
+        &mut settings.scaler(),
+        &settings.postprocess,
+        &mut hud,
+        &broadcast,
+    )?;
+    Ok(ExitCode::SUCCESS)
 }
 
-fn create_transition() -> anyhow::Result<IUIAnimationTransition> {
-    unsafe {
-        let library: IUIAnimationTransitionLibrary =
-            CoCreateInstance(&UIAnimationTransitionLibrary, None, CLSCTX_ALL)?;
-        Ok(library.CreateAccelerateDecelerateTransition(5.0, 1.0, 0.2, 0.8)?)
+fn bench(args: cli::BenchArgs) -> anyhow::Result<ExitCode> {
+    let mut options = plugin::Options::default();
+    options.spans = args.spans;
+    let (mut runner, settings) = load(&args.headless.demo, &options)?;
+    let (size, _) = set_up_headless(&mut runner, &settings, &args.headless)?;
+    // Only the guest is timed, at whatever size the render scale picks.
+    let mut scaler = settings.scaler();
+    let mut times = Vec::new();
+    for i in 0..args.warmup + args.frames {
+        let start = Instant::now();
+        runner.render_frame(i as f64 / 60.0, &scaler.render_size(&size))?;
+        let elapsed = start.elapsed();
+        scaler.record(elapsed);
+        if i >= args.warmup {
+            times.push(elapsed);
+        }
+    }
+    if times.is_empty() {
+        return Ok(ExitCode::SUCCESS);
     }
+    times.sort();
+    let ms = |time: Duration| time.as_secs_f64() * 1e3;
+    let mean = times.iter().sum::<Duration>() / times.len() as u32;
+    let rendered = scaler.render_size(&size);
+    println!(
+        "{} frames of {}x{} (render scale {})",
+        times.len(),
+        rendered.width,
+        rendered.height,
+        scaler.scale()
+    );
+    println!(
+        "mean   {:8.3}ms ({:.1} fps)",
+        ms(mean),
+        1.0 / mean.as_secs_f64()
+    );
+    println!("median {:8.3}ms", ms(times[times.len() / 2]));
+    println!(
+        "p95    {:8.3}ms",
+        ms(times[(times.len() * 95).div_ceil(100) - 1])
+    );
+    println!("min    {:8.3}ms", ms(times[0]));
+    println!("max    {:8.3}ms", ms(times[times.len() - 1]));
+    if let Some(profile) = runner.frame_profile() {
+        print!("\nlast frame:\n{profile}");
+    }
+    Ok(ExitCode::SUCCESS)
 }
 
-fn create_device_with_type(drive_type: D3D_DRIVER_TYPE) -> Result<ID3D11Device> {
-    let mut flags = D3D11_CREATE_DEVICE_BGRA_SUPPORT;
-
-    // if cfg!(debug_assertions) {
-    //     flags |= D3D11_CREATE_DEVICE_DEBUG;
-    // }
-
-    let mut device = None;
-
-    unsafe {
-        D3D11CreateDevice(
-            None,
-            drive_type,
-            None,
-            flags,
-            None,
-            D3D11_SDK_VERSION,
-            Some(&mut device),
-            None,
-            None,
-        )
-        .map(|()| device.unwrap())
+fn inspect(args: cli::InspectArgs) -> anyhow::Result<ExitCode> {
+    let bytes = std::fs::read(&args.module)
+        .with_context(|| format!("reading {}", args.module.display()))?;
+    let inspection =
+        abi::inspect(&bytes).with_context(|| format!("{} is not wasm", args.module.display()))?;
+    if args.json {
+        serde_json::to_writer_pretty(std::io::stdout().lock(), &inspection)?;
+        println!();
+        return Ok(ExitCode::SUCCESS);
+    }
+    println!("module: {}", args.module.display());
+    println!(
+        "abi version: {} (host supports up to {})",
+        inspection.abi_version,
+        abi::ABI_VERSION
+    );
+    println!("metadata:");
+    for (key, value) in &inspection.metadata {
+        println!("  {key} = {value}");
+    }
+    println!("exports:");
+    for item in &inspection.exports {
+        println!("  {}: {}", item.name, item.ty);
+    }
+    println!("imports:");
+    for item in &inspection.imports {
+        println!("  {}: {}", item.name, item.ty);
+    }
+    if !inspection.problems.is_empty() {
+        println!("problems:");
+        for problem in &inspection.problems {
+            println!("  {problem}");
+        }
     }
+    Ok(ExitCode::SUCCESS)
 }
 
-fn create_device() -> Result<ID3D11Device> {
-    let mut result = create_device_with_type(D3D_DRIVER_TYPE_HARDWARE);
-
-    if let Err(err) = &result {
-        match err.code() {
-            DXGI_ERROR_UNSUPPORTED | DXGI_ERROR_SDK_COMPONENT_MISSING => {
-                result = create_device_with_type(D3D_DRIVER_TYPE_WARP);
+fn validate(args: cli::ValidateArgs) -> anyhow::Result<ExitCode> {
+    let mut valid = true;
+    let options = plugin::Options::default();
+    let runtime = plugin::DemoRuntime::shared(&plugin::RuntimeOptions::default())?;
+    for path in &args.modules {
+        let problems = match std::fs::read(path)
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| Ok((abi::inspect(&bytes)?, bytes)))
+        {
+            // Following the ABI on paper isn't enough; the module also has
+            // to compile and instantiate under the policy `run` would use.
+            Ok((inspection, bytes)) if inspection.problems.is_empty() => {
+                let options = config_settings(path, &bytes, args.config.as_deref())
+                    .and_then(|settings| module_policy(path, &settings))
+                    .map(|policy| {
+                        let mut options = options.clone();
+                        options.policy = policy;
+                        options
+                    });
+                match options.and_then(|options| runtime.create_file(path, &options)) {
+                    Ok(_) => vec![],
+                    Err(error) => vec![format!("{error:#}")],
+                }
+            }
+            Ok((inspection, _)) => inspection.problems,
+            Err(error) => vec![format!("{error:#}")],
+        };
+        if problems.is_empty() {
+            println!("{}: ok", path.display());
+        } else {
+            valid = false;
+            println!("{}: invalid", path.display());
+            for problem in problems {
+                println!("  {problem}");
             }
-            _ => {}
         }
     }
-
-    result
+    Ok(if valid {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(EXIT_INVALID)
+    })
 }
 
-fn create_render_target(
-    factory: &ID2D1Factory1,
-    device: &ID3D11Device,
-) -> anyhow::Result<ID2D1DeviceContext> {
-    unsafe {
-        let d2device = factory.CreateDevice(&device.cast::<IDXGIDevice>()?)?;
-
-        let target = d2device.CreateDeviceContext(D2D1_DEVICE_CONTEXT_OPTIONS_NONE)?;
-
-        target.SetUnitMode(D2D1_UNIT_MODE_DIPS);
-
-        Ok(target)
+fn list(args: cli::ListArgs) -> anyhow::Result<ExitCode> {
+    let mut dirs = args.dirs;
+    if dirs.is_empty() {
+        dirs.push(".".into());
     }
+    let cache = catalogue::default_cache_path().filter(|_| !args.no_cache);
+    let catalogue = catalogue::Catalogue::scan(&dirs, cache.as_deref())?;
+    let stdout = std::io::stdout().lock();
+    if args.json {
+        catalogue.write_json(stdout)?;
+    } else {
+        catalogue.write_table(stdout)?;
+    }
+    Ok(ExitCode::SUCCESS)
 }
 
-fn get_dxgi_factory(device: &ID3D11Device) -> Result<IDXGIFactory2> {
-    let dxdevice = device.cast::<IDXGIDevice>()?;
-    unsafe { Ok(dxdevice.GetAdapter()?.GetParent()?) }
-}
-
-fn create_swapchain_bitmap(swapchain: &IDXGISwapChain1, target: &ID2D1DeviceContext) -> Result<()> {
-    let surface: IDXGISurface = unsafe { swapchain.GetBuffer(0)? };
-
-    let props = D2D1_BITMAP_PROPERTIES1 {
-        pixelFormat: D2D1_PIXEL_FORMAT {
-            format: DXGI_FORMAT_B8G8R8A8_UNORM,
-            alphaMode: D2D1_ALPHA_MODE_IGNORE,
-        },
-        dpiX: 96.0,
-        dpiY: 96.0,
-        bitmapOptions: D2D1_BITMAP_OPTIONS_TARGET | D2D1_BITMAP_OPTIONS_CANNOT_DRAW,
-        ..Default::default()
-    };
-
-    unsafe {
-        let bitmap = target.CreateBitmapFromDxgiSurface(&surface, Some(&props))?;
-        target.SetTarget(&bitmap);
-    };
-
-    Ok(())
-}
-
-fn create_swapchain(device: &ID3D11Device, window: HWND) -> Result<IDXGISwapChain1> {
-    let factory = get_dxgi_factory(device)?;
-
-    let props = DXGI_SWAP_CHAIN_DESC1 {
-        Format: DXGI_FORMAT_B8G8R8A8_UNORM,
-        SampleDesc: DXGI_SAMPLE_DESC {
-            Count: 1,
-            Quality: 0,
-        },
-        BufferUsage: DXGI_USAGE_RENDER_TARGET_OUTPUT,
-        BufferCount: 2,
-        SwapEffect: DXGI_SWAP_EFFECT_FLIP_SEQUENTIAL,
-        ..Default::default()
+fn export(args: cli::ExportArgs) -> anyhow::Result<ExitCode> {
+    let module = if args.module.is_dir() {
+        args.module.join("demo.wasm")
+    } else {
+        args.module.clone()
     };
-
-    unsafe { factory.CreateSwapChainForHwnd(device, window, &props, None, None) }
+    let bytes = std::fs::read(&module).with_context(|| format!("reading {}", module.display()))?;
+    let mut settings = config_settings(&module, &bytes, args.config.as_deref())?;
+    if let Some(width) = args.width {
+        settings.size.width = width;
+    }
+    if let Some(height) = args.height {
+        settings.size.height = height;
+    }
+    settings.params.extend(args.params);
+    let name = catalogue::module_name(&module);
+    let page = export::html(&bytes, &name, &settings.size, &settings.params)
+        .with_context(|| format!("exporting {}", module.display()))?;
+    let out = args.out.unwrap_or_else(|| format!("{name}.html").into());
+    std::fs::write(&out, page).with_context(|| format!("writing {}", out.display()))?;
+    println!("wrote {}", out.display());
+    Ok(ExitCode::SUCCESS)
 }
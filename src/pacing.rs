@@ -0,0 +1,279 @@
+use serde::Deserialize;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// How a capped presenter passes the time until the next frame is due.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum Wait {
+    /// Let the thread sleep. Cheap, but only as precise as the system's
+    /// timer.
+    #[default]
+    Sleep,
+    /// Keep yielding to other threads. Precise, but keeps a core busy.
+    Yield,
+}
+
+impl Wait {
+    fn until(self, deadline: Instant) {
+        match self {
+            Wait::Sleep => {
+                if let Some(wait) = deadline.checked_duration_since(Instant::now()) {
+                    std::thread::sleep(wait);
+                }
+            }
+            Wait::Yield => {
+                while Instant::now() < deadline {
+                    std::thread::yield_now();
+                }
+            }
+        }
+    }
+}
+
+/// Checks a frame rate cap from the command line or the config file, where
+/// 0 means no cap.
+pub fn check_max_fps(fps: f64) -> Result<f64, String> {
+    if fps.is_finite() && fps >= 0.0 {
+        Ok(fps)
+    } else {
+        Err(format!("expected a frame rate of 0 or more, got {fps}"))
+    }
+}
+
+/// Where a `Pacer` gets the time from, and how it waits; the system clock
+/// outside of tests.
+trait Clock {
+    fn now(&mut self) -> Instant;
+    fn wait_until(&mut self, wait: Wait, deadline: Instant);
+}
+
+struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&mut self) -> Instant {
+        Instant::now()
+    }
+
+    fn wait_until(&mut self, wait: Wait, deadline: Instant) {
+        wait.until(deadline);
+    }
+}
+
+/// What a `Pacer` has seen so far.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[non_exhaustive]
+pub struct Stats {
+    pub frames: u64,
+    /// Frame times that went by without a frame because the ones before ran
+    /// late. Where demo time follows the clock they are skipped over, and
+    /// otherwise the demo slows down.
+    pub missed: u64,
+    /// From the first frame to the last.
+    pub elapsed: Duration,
+    /// The longest time between two frames.
+    pub worst: Duration,
+    /// How long was spent waiting for the cap.
+    pub waited: Duration,
+}
+
+impl Stats {
+    pub fn fps(&self) -> f64 {
+        match self.elapsed.as_secs_f64() {
+            elapsed if elapsed > 0.0 => self.frames.saturating_sub(1) as f64 / elapsed,
+            _ => 0.0,
+        }
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} frames in {:.2} s ({:.1} fps), worst {:.1} ms, {} missed, {:.2} s waiting",
+            self.frames,
+            self.elapsed.as_secs_f64(),
+            self.fps(),
+            self.worst.as_secs_f64() * 1e3,
+            self.missed,
+            self.waited.as_secs_f64()
+        )
+    }
+}
+
+/// Spaces frames out to a frame rate cap, if there is one, and keeps
+/// `Stats` on how well frames keep to it.
+#[derive(Clone, Debug)]
+pub struct Pacer {
+    /// Time between frames under the cap.
+    cap: Option<Duration>,
+    /// Time between frames that counts as on time.
+    period: Duration,
+    wait: Wait,
+    /// When the next frame is due under the cap.
+    next: Option<Instant>,
+    last: Option<Instant>,
+    /// Time since the frame before the last.
+    gap: Option<Duration>,
+    stats: Stats,
+}
+
+impl Pacer {
+    /// Caps frames at `max_fps` per second, waiting in between as `wait`
+    /// says, or doesn't cap them if `max_fps` is 0. Uncapped frames are on
+    /// time if they are no more than `period` apart.
+    pub fn new(max_fps: f64, wait: Wait, period: Duration) -> Pacer {
+        let cap = (max_fps > 0.0)
+            .then(|| Duration::from_secs_f64(1.0 / max_fps))
+            .filter(|cap| !cap.is_zero());
+        Pacer {
+            cap,
+            period: cap.unwrap_or(period),
+            wait,
+            next: None,
+            last: None,
+            gap: None,
+            stats: Stats::default(),
+        }
+    }
+
+    /// The time between frames that counts as on time.
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// How far demo time moves for the last frame where it doesn't follow
+    /// the clock: the time since the frame before, up to a period, so that
+    /// slow frames slow the demo down instead of skipping ahead and fast
+    /// ones, e.g. under vsync above the target, don't speed it up.
+    pub fn frame_time(&self) -> Duration {
+        match self.gap {
+            Some(gap) if !self.period.is_zero() => gap.min(self.period),
+            Some(gap) => gap,
+            None => self.period,
+        }
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// Waits until the next frame is due, and counts it.
+    pub fn wait(&mut self) {
+        self.wait_on(&mut SystemClock);
+    }
+
+    fn wait_on(&mut self, clock: &mut impl Clock) {
+        if let Some(next) = self.next {
+            let start = clock.now();
+            if next > start {
+                clock.wait_until(self.wait, next);
+                self.stats.waited += clock.now().duration_since(start);
+            }
+        }
+        let now = clock.now();
+        self.frame_at(now);
+    }
+
+    fn frame_at(&mut self, now: Instant) {
+        if let Some(last) = self.last {
+            let gap = now.duration_since(last);
+            self.gap = Some(gap);
+            self.stats.elapsed += gap;
+            self.stats.worst = self.stats.worst.max(gap);
+            // Half a period late is still in time for the next frame. Without
+            // a period, no frame is ever late.
+            if !self.period.is_zero() {
+                let frames = (gap.as_secs_f64() / self.period.as_secs_f64()).round() as u64;
+                self.stats.missed = self.stats.missed.saturating_add(frames.saturating_sub(1));
+            }
+        }
+        self.stats.frames += 1;
+        self.last = Some(now);
+        if let Some(cap) = self.cap {
+            // Keep to the schedule, so that the frame rate averages out to
+            // the cap, unless a whole frame late.
+            let next = self.next.unwrap_or(now) + cap;
+            self.next = Some(if next > now { next } else { now + cap });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Only moves when told to, and jumps ahead when waiting.
+    struct FakeClock {
+        now: Instant,
+        waits: Vec<Wait>,
+    }
+
+    impl Clock for FakeClock {
+        fn now(&mut self) -> Instant {
+            self.now
+        }
+
+        fn wait_until(&mut self, wait: Wait, deadline: Instant) {
+            self.waits.push(wait);
+            self.now = self.now.max(deadline);
+        }
+    }
+
+    #[test]
+    fn test_pacer() {
+        let mut pacer = Pacer::new(0.0, Wait::Sleep, Duration::from_millis(10));
+        assert_eq!(pacer.period(), Duration::from_millis(10));
+        let start = Instant::now();
+        // Frames stand for the time they took, but no more than a period.
+        let mut frame_times = Vec::new();
+        for millis in [0, 10, 21, 45, 55, 59] {
+            pacer.frame_at(start + Duration::from_millis(millis));
+            frame_times.push(pacer.frame_time().as_millis());
+        }
+        assert_eq!(frame_times, [10, 10, 10, 10, 10, 4]);
+        assert_eq!(pacer.next, None);
+        let stats = pacer.stats();
+        assert_eq!((stats.frames, stats.missed), (6, 1));
+        assert_eq!(stats.worst, Duration::from_millis(24));
+        assert_eq!(stats.fps().round(), 85.0);
+
+        let mut capped = Pacer::new(100.0, Wait::Sleep, Duration::from_secs(1));
+        assert_eq!(capped.period(), Duration::from_millis(10));
+        capped.frame_at(start);
+        capped.frame_at(start + Duration::from_millis(12));
+        assert_eq!(capped.next, Some(start + Duration::from_millis(20)));
+        capped.frame_at(start + Duration::from_millis(35));
+        assert_eq!(capped.next, Some(start + Duration::from_millis(45)));
+
+        // Uncapped frames with no period to keep to are never late.
+        let mut unpaced = Pacer::new(0.0, Wait::Sleep, Duration::ZERO);
+        unpaced.frame_at(start);
+        unpaced.frame_at(start + Duration::from_secs(1));
+        assert_eq!((unpaced.stats().frames, unpaced.stats().missed), (2, 0));
+        assert_eq!(unpaced.frame_time(), Duration::from_secs(1));
+        let infinite = Pacer::new(f64::INFINITY, Wait::Sleep, Duration::ZERO);
+        assert_eq!(infinite.cap, None);
+        assert!(check_max_fps(-1.0).is_err() && check_max_fps(f64::NAN).is_err());
+        assert_eq!(check_max_fps(0.0), Ok(0.0));
+
+        for wait in [Wait::Sleep, Wait::Yield] {
+            let mut pacer = Pacer::new(200.0, wait, Duration::ZERO);
+            let mut clock = FakeClock {
+                now: start,
+                waits: Vec::new(),
+            };
+            for _ in 0..5 {
+                pacer.wait_on(&mut clock);
+                // Rendering takes 2 ms of the 5 ms between frames.
+                clock.now += Duration::from_millis(2);
+            }
+            assert_eq!(clock.waits, [wait; 4]);
+            let stats = pacer.stats();
+            assert_eq!(stats.elapsed, Duration::from_millis(20));
+            assert_eq!(stats.waited, Duration::from_millis(12));
+            assert_eq!(stats.missed, 0);
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use std::time::Instant;
+
+/// The demo's clock, in seconds, which can be paused and moved around.
+///
+/// ```
+/// let mut playback = demo::Playback::default();
+/// playback.pause();
+/// playback.seek(12.5);
+/// assert_eq!(playback.time(), 12.5);
+/// ```
+pub struct Playback {
+    playing: bool,
+    /// Whether demo time only moves on `advance` instead of with the clock.
+    frame_locked: bool,
+    speed: f64,
+    /// The time at `anchor`.
+    position: f64,
+    anchor: Instant,
+}
+
+impl Default for Playback {
+    fn default() -> Self {
+        Playback {
+            playing: true,
+            frame_locked: false,
+            speed: 1.0,
+            position: 0.0,
+            anchor: Instant::now(),
+        }
+    }
+}
+
+impl Playback {
+    pub fn time(&self) -> f64 {
+        self.time_at(Instant::now())
+    }
+
+    fn time_at(&self, now: Instant) -> f64 {
+        if self.playing && !self.frame_locked {
+            self.position + now.duration_since(self.anchor).as_secs_f64() * self.speed
+        } else {
+            self.position
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    pub fn play(&mut self) {
+        self.set_playing(true, Instant::now());
+    }
+
+    pub fn pause(&mut self) {
+        self.set_playing(false, Instant::now());
+    }
+
+    fn set_playing(&mut self, playing: bool, now: Instant) {
+        self.position = self.time_at(now);
+        self.anchor = now;
+        self.playing = playing;
+    }
+
+    /// How many seconds of demo time pass per second.
+    pub fn set_speed(&mut self, speed: f64) {
+        let now = Instant::now();
+        self.position = self.time_at(now);
+        self.anchor = now;
+        self.speed = speed;
+    }
+
+    /// Ties demo time to frames: while playing, it only moves when
+    /// `advance` is called, however long frames take.
+    pub fn set_frame_locked(&mut self, locked: bool) {
+        let now = Instant::now();
+        self.position = self.time_at(now);
+        self.anchor = now;
+        self.frame_locked = locked;
+    }
+
+    /// Moves a frame-locked demo on by a frame `seconds` long, if it is
+    /// playing.
+    pub fn advance(&mut self, seconds: f64) {
+        if self.playing && self.frame_locked {
+            self.position += seconds * self.speed;
+        }
+    }
+
+    pub fn seek(&mut self, time: f64) {
+        self.position = time;
+        self.anchor = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_playback() {
+        let mut playback = Playback::default();
+        let start = playback.anchor;
+        let at = |millis| start + Duration::from_millis(millis);
+        assert_eq!(playback.time_at(at(500)), 0.5);
+        playback.set_playing(false, at(1000));
+        assert_eq!(playback.time_at(at(3000)), 1.0);
+        playback.seek(10.0);
+        assert!(!playback.is_playing());
+        assert_eq!(playback.time(), 10.0);
+        playback.set_playing(true, at(4000));
+        assert_eq!(playback.time_at(at(4250)), 10.25);
+        playback.speed = 2.0;
+        assert_eq!(playback.time_at(at(4500)), 11.0);
+
+        playback.advance(1.0);
+        assert_eq!(playback.time_at(at(4500)), 11.0);
+        playback.frame_locked = true;
+        playback.position = 11.0;
+        playback.advance(0.25);
+        assert_eq!(playback.time_at(at(9000)), 11.5);
+        playback.pause();
+        playback.advance(0.25);
+        assert_eq!(playback.time(), 11.5);
+    }
+}
@@ -1,70 +1,976 @@
-use anyhow::{Context, Result};
+use crate::profile::{FrameProfile, SpanRecorder};
+use crate::replay::{self, Event, Replay};
+use anyhow::Context;
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::ffi::CStr;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use wasmtime::*;
 
+/// Receives guest output and host messages about the guest, one line at a
+/// time, in addition to them being printed.
+pub type LogHandler = Arc<dyn Fn(&str) + Send + Sync>;
+
 #[derive(Default)]
-pub struct StoreState {}
+struct StoreState {
+    spans: Option<SpanRecorder>,
+    log: Option<LogHandler>,
+}
+
+impl StoreState {
+    fn log(&self, message: &str) {
+        println!("{message}");
+        if let Some(log) = &self.log {
+            log(message);
+        }
+    }
+}
 
 pub struct DemoRunner {
+    path: Option<PathBuf>,
+    runtime: DemoRuntime,
+    /// The SHA-1 of the module.
+    hash: [u8; 20],
+    pre: InstancePre<StoreState>,
+    options: Options,
     instance: wasmtime::Instance,
     store: wasmtime::Store<StoreState>,
+    memory: wasmtime::Memory,
+    dpi: Option<i32>,
+    resolution: Resolution,
+    framebuffer_size: Option<Rect>,
+    /// Where `resize_framebuffer` said the next frame goes.
+    framebuffer: Option<usize>,
+    invalidated: bool,
+    frame: Option<(usize, Rect)>,
+    damage: Vec<Region>,
+    params: BTreeMap<String, f64>,
+    metadata: BTreeMap<String, String>,
+    /// Calls into the guest since `start_recording`.
+    recording: Option<Vec<Event>>,
+}
+
+/// Settings for a `DemoRuntime`, shared by every runner it creates.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RuntimeOptions {
+    /// Load the DWARF debug info of guests built with it, so traps and guest
+    /// panics are reported with function names, files and lines, and native
+    /// debuggers can step through guest code.
+    pub debug_info: bool,
+    /// Emit symbols for JIT-compiled guest code for `perf` on Linux.
+    pub profiler: Profiler,
+    /// Set aside memory for this many guest instances up front, so creating
+    /// and restarting runners is cheaper. No more can exist at once, and a
+    /// runner briefly needs a second one while it restarts.
+    pub pool_size: Option<u32>,
+    /// Give NaNs the same bits on every CPU, so that replays match frames
+    /// recorded on another machine. Float-heavy guests run a little slower.
+    pub canonicalize_nans: bool,
+}
+
+/// Host settings for loading a demo into a runner.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Summarize the guest's `span_begin`/`span_end` spans every frame.
+    pub spans: bool,
+    /// Keep spans for `write_chrome_trace`, up to `profile::TRACE_LIMIT`.
+    /// Implies `spans`.
+    pub trace: bool,
+    /// The host imports the guest may link to.
+    pub policy: Policy,
+}
+
+/// A group of host imports that a guest may be allowed to use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum Capability {
+    /// Printing output and marking profiling spans.
+    Logging,
+    // No host import needs the others yet. Policies can already name them,
+    // so configs and manifests don't have to change when imports arrive.
+    Assets,
+    Storage,
+    Audio,
+    Clock,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 5] = [
+        Capability::Logging,
+        Capability::Assets,
+        Capability::Storage,
+        Capability::Audio,
+        Capability::Clock,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Capability::Logging => "logging",
+            Capability::Assets => "assets",
+            Capability::Storage => "storage",
+            Capability::Audio => "audio",
+            Capability::Clock => "clock",
+        }
+    }
+}
+
+/// Which capabilities a guest may use. Guests importing anything else are
+/// refused before they are instantiated.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Policy {
+    allowed: BTreeSet<Capability>,
+}
+
+impl Default for Policy {
+    /// Allows everything.
+    fn default() -> Self {
+        Policy::allow(Capability::ALL)
+    }
+}
+
+impl Policy {
+    pub fn allow(capabilities: impl IntoIterator<Item = Capability>) -> Policy {
+        Policy {
+            allowed: capabilities.into_iter().collect(),
+        }
+    }
+
+    pub fn allows(&self, capability: Capability) -> bool {
+        self.allowed.contains(&capability)
+    }
+
+    /// What both policies allow.
+    pub fn intersect(&self, other: &Policy) -> Policy {
+        Policy {
+            allowed: self.allowed.intersection(&other.allowed).copied().collect(),
+        }
+    }
+
+    /// Explains every import of `module` that the policy doesn't allow.
+    fn check(&self, module: &Module) -> anyhow::Result<()> {
+        let mut problems = Vec::new();
+        for import in module.imports() {
+            let name = format!("{}.{}", import.module(), import.name());
+            match crate::abi::import_capability(import.module(), import.name()) {
+                None => problems.push(format!("it imports {name}, which the host doesn't provide")),
+                Some(capability) if !self.allows(capability) => problems.push(format!(
+                    "it imports {name}, which needs the `{}` capability",
+                    capability.name()
+                )),
+                Some(_) => {}
+            }
+        }
+        if problems.is_empty() {
+            return Ok(());
+        }
+        let allowed: Vec<&str> = self.allowed.iter().map(Capability::name).collect();
+        anyhow::bail!(
+            "refusing to link the demo: {}; it may only use {}",
+            problems.join("; "),
+            if allowed.is_empty() {
+                "no capabilities".to_string()
+            } else {
+                allowed.join(", ")
+            }
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Profiler {
+    #[default]
+    None,
+    /// `/tmp/perf-<pid>.map`, for `perf report`.
+    PerfMap,
+    /// `jit-<pid>.dump` in the working directory, for `perf inject --jit`.
+    JitDump,
 }
 
 pub fn create_file<P>(path: P) -> anyhow::Result<DemoRunner>
 where
     P: AsRef<Path>,
 {
-    // First the wasm module needs to be compiled. This is done with a global
-    // "compilation environment" within an `Engine`. Note that engines can be
-    // further configured through `Config` if desired instead of using the
-    // default like this is here.
-    // println!("Compiling module...");
-    let engine = Engine::default();
-    let module = Module::from_file(&engine, path)?;
+    create_file_with_options(path, &Options::default())
+}
+
+/// Loads a demo into the process-wide default runtime. Use a `DemoRuntime`
+/// for debug info, profiling or a pooling allocator.
+pub fn create_file_with_options<P>(path: P, options: &Options) -> anyhow::Result<DemoRunner>
+where
+    P: AsRef<Path>,
+{
+    DemoRuntime::shared(&RuntimeOptions::default())?.create_file(path, options)
+}
+
+/// Loads a module in the binary or the text format from memory. Such a
+/// runner can't `reload`.
+pub fn create_bytes(bytes: &[u8]) -> anyhow::Result<DemoRunner> {
+    create_bytes_with_options(bytes, &Options::default())
+}
+
+pub fn create_bytes_with_options(bytes: &[u8], options: &Options) -> anyhow::Result<DemoRunner> {
+    DemoRuntime::shared(&RuntimeOptions::default())?.create_bytes(bytes, options)
+}
+
+/// Loads a module written in the WebAssembly text format, e.g. a tiny guest
+/// inline in a test:
+///
+/// ```
+/// let mut runner = demo::create_wat(
+///     r#"(module
+///         (memory (export "memory") 1)
+///         (func (export "render") (param f64 i32 i32) (result i32) i32.const 0))"#,
+/// )?;
+/// let frame = runner.render_frame(0.0, &demo::Rect { width: 4, height: 4 })?;
+/// assert_eq!(frame.data, [0; 64]);
+/// # anyhow::Ok(())
+/// ```
+pub fn create_wat(text: &str) -> anyhow::Result<DemoRunner> {
+    create_wat_with_options(text, &Options::default())
+}
+
+pub fn create_wat_with_options(text: &str, options: &Options) -> anyhow::Result<DemoRunner> {
+    create_bytes_with_options(&wat::parse_str(text)?, options)
+}
+
+/// Compiles and links demos. Runners from the same runtime share its
+/// `Engine` and host imports, and a module is only compiled the first time
+/// the runtime sees it, so running many copies of a demo, or previews of a
+/// whole catalogue, is cheap:
+///
+/// ```
+/// # let path = std::env::temp_dir().join("demo-doc-runtime.wat");
+/// # std::fs::write(&path, r#"(module
+/// #     (memory (export "memory") 1)
+/// #     (func (export "render") (param f64 i32 i32) (result i32) i32.const 0))"#)?;
+/// use demo::{DemoRuntime, Options, RuntimeOptions};
+///
+/// let options = Options::default();
+/// let runtime = DemoRuntime::new(&RuntimeOptions::default())?;
+/// let runners = (0..4)
+///     .map(|_| runtime.create_file(&path, &options))
+///     .collect::<anyhow::Result<Vec<_>>>()?;
+/// # anyhow::Ok(())
+/// ```
+///
+/// Clones share the engine and the compiled modules.
+#[derive(Clone)]
+pub struct DemoRuntime {
+    engine: Engine,
+    linker: Arc<Linker<StoreState>>,
+    /// Compiled modules by the SHA-1 of their bytes.
+    modules: Arc<Mutex<HashMap<[u8; 20], Module>>>,
+}
+
+impl DemoRuntime {
+    /// The process-wide runtime for `options`, set up the first time it is
+    /// asked for. Modules compiled by it stay cached until the process exits.
+    pub fn shared(options: &RuntimeOptions) -> anyhow::Result<DemoRuntime> {
+        static SHARED: Mutex<Vec<(RuntimeOptions, DemoRuntime)>> = Mutex::new(Vec::new());
+        let mut shared = SHARED.lock().unwrap();
+        if let Some((_, runtime)) = shared.iter().find(|(o, _)| o == options) {
+            return Ok(runtime.clone());
+        }
+        let runtime = DemoRuntime::new(options)?;
+        shared.push((options.clone(), runtime.clone()));
+        Ok(runtime)
+    }
+
+    pub fn new(options: &RuntimeOptions) -> anyhow::Result<DemoRuntime> {
+        // Wasm modules are compiled within a global "compilation
+        // environment", an `Engine`. Linear memory is always reserved up
+        // front so that it never moves when the guest grows it, which keeps
+        // `FrameView`s pointing at the same host addresses across frames.
+        let mut config = Config::new();
+        config.static_memory_forced(true);
+        config.cranelift_nan_canonicalization(options.canonicalize_nans);
+        if options.debug_info {
+            config.debug_info(true);
+            config.wasm_backtrace_details(WasmBacktraceDetails::Enable);
+        }
+        config.profiler(match options.profiler {
+            Profiler::None => ProfilingStrategy::None,
+            Profiler::PerfMap => ProfilingStrategy::PerfMap,
+            Profiler::JitDump => ProfilingStrategy::JitDump,
+        });
+        if let Some(size) = options.pool_size {
+            let mut pooling = PoolingAllocationConfig::default();
+            pooling
+                .total_core_instances(size)
+                .total_memories(size)
+                .total_tables(size)
+                // Pooled guests may grow their memory to the full 4 GiB of
+                // wasm32, like other guests.
+                .memory_pages(1 << 16);
+            config.allocation_strategy(InstanceAllocationStrategy::Pooling(pooling));
+        }
+        let engine = Engine::new(&config)?;
 
+        // Host functions are registered by name in a `Linker`, so guests only
+        // have to import the ones they use, in any order. The `caller`
+        // parameter gives access to the guest's memory and our `StoreState`.
+        let mut linker = Linker::new(&engine);
+        linker.func_wrap(
+            "env",
+            "output",
+            |mut caller: Caller<'_, StoreState>, str: i32| {
+                let rstr = read_c_string(&mut caller, str);
+                if rstr.starts_with("panicked at ") {
+                    // Guests report panics through their panic hook; the
+                    // stack that led there is still live, so capture it.
+                    let backtrace = WasmBacktrace::capture(&caller);
+                    caller.data().log(&format!("{rstr}\n{backtrace}"));
+                } else {
+                    caller.data().log(&rstr);
+                }
+            },
+        )?;
+        linker.func_wrap(
+            "env",
+            "span_begin",
+            |mut caller: Caller<'_, StoreState>, name: i32| {
+                if caller.data().spans.is_none() {
+                    return;
+                }
+                let Some(Extern::Memory(mem)) = caller.get_export("memory") else {
+                    return;
+                };
+                // Borrowed from guest memory; spans are too frequent to copy.
+                let (data, state) = mem.data_and_store_mut(&mut caller);
+                let bytes = data.get(name as u32 as usize..).unwrap_or_default();
+                let name = CStr::from_bytes_until_nul(bytes).unwrap_or_default();
+                if let Some(spans) = &mut state.spans {
+                    spans.begin(&String::from_utf8_lossy(name.to_bytes()));
+                }
+            },
+        )?;
+        linker.func_wrap("env", "span_end", |mut caller: Caller<'_, StoreState>| {
+            if let Some(spans) = &mut caller.data_mut().spans {
+                spans.end();
+            }
+        })?;
+
+        Ok(DemoRuntime {
+            engine,
+            linker: Arc::new(linker),
+            modules: Default::default(),
+        })
+    }
+
+    /// Loads the module at `path` with the per-runner fields of `options`.
+    pub fn create_file(
+        &self,
+        path: impl AsRef<Path>,
+        options: &Options,
+    ) -> anyhow::Result<DemoRunner> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        self.create(&bytes, Some(path.to_owned()), options)
+    }
+
+    /// Loads a module in the binary or the text format from memory, with the
+    /// per-runner fields of `options`. Such a runner can't `reload`.
+    pub fn create_bytes(&self, bytes: &[u8], options: &Options) -> anyhow::Result<DemoRunner> {
+        self.create(bytes, None, options)
+    }
+
+    fn create(
+        &self,
+        bytes: &[u8],
+        path: Option<PathBuf>,
+        options: &Options,
+    ) -> anyhow::Result<DemoRunner> {
+        let (hash, module) = self.compile(bytes)?;
+        let pre = self.link(&module, options)?;
+        let metadata = crate::abi::metadata(bytes)?;
+        let (store, instance, memory) = instantiate(&pre, options)?;
+        Ok(DemoRunner {
+            path,
+            runtime: self.clone(),
+            hash,
+            pre,
+            options: options.clone(),
+            instance,
+            store,
+            memory,
+            dpi: None,
+            resolution: Resolution::default(),
+            framebuffer_size: None,
+            framebuffer: None,
+            invalidated: true,
+            frame: None,
+            damage: Vec::new(),
+            params: BTreeMap::new(),
+            metadata,
+            recording: None,
+        })
+    }
+
+    fn compile(&self, bytes: &[u8]) -> anyhow::Result<([u8; 20], Module)> {
+        let hash = Sha1::digest(bytes).into();
+        if let Some(module) = self.modules.lock().unwrap().get(&hash) {
+            return Ok((hash, module.clone()));
+        }
+        // Compiling takes a while, and other threads may want other modules
+        // meanwhile.
+        let module = Module::new(&self.engine, bytes)?;
+        self.modules.lock().unwrap().insert(hash, module.clone());
+        Ok((hash, module))
+    }
+
+    /// Checks `module` against the policy and resolves its imports.
+    fn link(&self, module: &Module, options: &Options) -> anyhow::Result<InstancePre<StoreState>> {
+        options.policy.check(module)?;
+        self.linker.instantiate_pre(module)
+    }
+}
+
+fn instantiate(
+    pre: &InstancePre<StoreState>,
+    options: &Options,
+) -> anyhow::Result<(Store<StoreState>, Instance, Memory)> {
     // After a module is compiled we create a `Store` which will contain
     // instantiated modules and other items like host functions. A Store
-    // contains an arbitrary piece of host information, and we use `MyState`
-    // here.
-    // println!("Initializing...");
-    let mut store = Store::new(&engine, Default::default());
-
-    // // Our wasm module we'll be instantiating requires one imported function.
-    // // the function takes no parameters and returns no results. We create a host
-    // // implementation of that function here, and the `caller` parameter here is
-    // // used to get access to our original `MyState` value.
-    // println!("Creating callback...");
-    let output_func = Func::wrap(
-        &mut store,
-        |mut caller: Caller<'_, StoreState>, str: i32| {
-            if let Some(Extern::Memory(mem)) = caller.get_export("memory") {
-                let cstr =
-                    CStr::from_bytes_until_nul(&mem.data(caller.as_context())[str as usize..])
-                        .unwrap_or_default();
-                let rstr = String::from_utf8_lossy(cstr.to_bytes()).to_string();
-                println!("{rstr}");
-            }
-        },
-    );
+    // contains an arbitrary piece of host information, and we use
+    // `StoreState` here.
+    let state = StoreState {
+        spans: (options.spans || options.trace).then(|| SpanRecorder::new(options.trace)),
+        log: None,
+    };
+    let mut store = Store::new(pre.module().engine(), state);
+
+    // The imports were paired with the linker's host functions up front, so
+    // this is where the wasm `start` function, if any, runs.
+    let instance = pre.instantiate(&mut store)?;
+    let memory = instance
+        .get_memory(&mut store, "memory")
+        .context("no memory")?;
+    Ok((store, instance, memory))
+}
 
-    // Once we've got that all set up we can then move to the instantiation
-    // phase, pairing together a compiled module as well as a set of imports.
-    // Note that this is where the wasm `start` function, if any, would run.
-    // println!("Instantiating module...");
-    let imports = [output_func.into()];
-    // let imports=[];
-    let instance = Instance::new(&mut store, &module, &imports)?;
-    Ok(DemoRunner { instance, store })
+fn read_c_string(caller: &mut Caller<'_, StoreState>, ptr: i32) -> String {
+    let Some(Extern::Memory(mem)) = caller.get_export("memory") else {
+        return String::new();
+    };
+    let bytes = mem
+        .data(caller.as_context())
+        .get(ptr as u32 as usize..)
+        .unwrap_or_default();
+    let cstr = CStr::from_bytes_until_nul(bytes).unwrap_or_default();
+    String::from_utf8_lossy(cstr.to_bytes()).to_string()
 }
 
 #[repr(C)]
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Rect {
     pub width: i32,
     pub height: i32,
 }
+
+/// The size limits hosts pass to `call_set_dimensions`, in logical pixels.
+pub const MIN_SIZE: Rect = Rect {
+    width: 100,
+    height: 100,
+};
+pub const MAX_SIZE: Rect = Rect {
+    width: 2560,
+    height: 1440,
+};
+
+/// The DPI at which one logical pixel is one physical pixel.
+pub const BASE_DPI: i32 = 96;
+
+/// Ratio of physical to logical pixels at `dpi`.
+pub fn scale_factor(dpi: i32) -> f64 {
+    dpi as f64 / BASE_DPI as f64
+}
+
+/// The resolution a guest wants to render at on high-DPI displays.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Resolution {
+    /// One frame pixel per physical pixel.
+    #[default]
+    Physical,
+    /// One frame pixel per logical pixel, stretched by the presenter.
+    Logical,
+}
+
+impl Resolution {
+    /// The frame size for an output that is `logical` pixels big at `dpi`.
+    /// Every presenter, native or web, sizes frames with this rule.
+    ///
+    /// ```
+    /// use demo::{Rect, Resolution};
+    ///
+    /// let logical = Rect { width: 640, height: 480 };
+    /// let size = Resolution::Physical.render_size(&logical, 144);
+    /// assert_eq!(size, Rect { width: 960, height: 720 });
+    /// ```
+    pub fn render_size(&self, logical: &Rect, dpi: i32) -> Rect {
+        match self {
+            Resolution::Physical => {
+                let scale = scale_factor(dpi);
+                Rect {
+                    width: ((logical.width as f64 * scale).round() as i32).max(1),
+                    height: ((logical.height as f64 * scale).round() as i32).max(1),
+                }
+            }
+            Resolution::Logical => logical.clone(),
+        }
+    }
+}
+
+/// An area of a frame, in pixels, that changed since the previous frame.
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Region {
+    pub fn full(size: &Rect) -> Region {
+        Region {
+            x: 0,
+            y: 0,
+            width: size.width,
+            height: size.height,
+        }
+    }
+
+    fn clip(&self, size: &Rect) -> Option<Region> {
+        let left = self.x.clamp(0, size.width);
+        let top = self.y.clamp(0, size.height);
+        let right = self.x.saturating_add(self.width).clamp(0, size.width);
+        let bottom = self.y.saturating_add(self.height).clamp(0, size.height);
+        (right > left && bottom > top).then(|| Region {
+            x: left,
+            y: top,
+            width: right - left,
+            height: bottom - top,
+        })
+    }
+}
+
+/// A value the guest lets the host tune while it runs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Param {
+    pub name: String,
+    pub value: f64,
+}
+
+/// Something the user did. Guests receive input through optional exports:
+/// `pointer_move(x, y)`, `pointer_button(button, pressed)` and
+/// `key(code, pressed)`, with `pressed` 1 or 0. Guests without them ignore
+/// the input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Input {
+    /// The pointer moved to `x, y`, in frame pixels from the top-left corner.
+    PointerMove { x: i32, y: i32 },
+    /// Button 0 is the primary button, 1 the secondary and 2 the middle one.
+    PointerButton { button: i32, pressed: bool },
+    /// `code` is a virtual-key code as on Windows and in the web's
+    /// `KeyboardEvent.keyCode`, so letters and digits are their uppercase
+    /// ASCII values.
+    Key { code: i32, pressed: bool },
+}
+
+/// Layout of the pixels in a frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PixelFormat {
+    /// Four bytes per pixel in `r, g, b, a` order.
+    Rgba8,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            PixelFormat::Rgba8 => 4,
+        }
+    }
+}
+
+/// The most recently rendered frame, borrowed in place from guest memory.
+///
+/// Guest memory never moves on the host, so presenters can keep reading the
+/// same address from frame to frame until the guest is asked to resize its
+/// framebuffer. Guests that double-buffer alternate between two addresses and
+/// leave the previous frame untouched while rendering the next one.
+#[non_exhaustive]
+pub struct FrameView<'a> {
+    pub data: &'a [u8],
+    pub size: Rect,
+    /// Distance in bytes between the starts of two rows.
+    pub stride: usize,
+    pub format: PixelFormat,
+    /// Regions that changed since the previous frame.
+    pub damage: &'a [Region],
+}
+
+impl FrameView<'_> {
+    /// The bytes of `region`, from its top-left pixel to its bottom-right one,
+    /// or `None` if it is empty or not entirely inside the frame.
+    pub fn region_data(&self, region: &Region) -> Option<&[u8]> {
+        if region.clip(&self.size).as_ref() != Some(region) {
+            return None;
+        }
+        // Inside the frame, so none of these are negative.
+        let (x, y) = (region.x as usize, region.y as usize);
+        let (width, height) = (region.width as usize, region.height as usize);
+        let bpp = self.format.bytes_per_pixel();
+        let start = y
+            .checked_mul(self.stride)?
+            .checked_add(x.checked_mul(bpp)?)?;
+        let end = (y + height - 1)
+            .checked_mul(self.stride)?
+            .checked_add((x + width).checked_mul(bpp)?)?;
+        self.data.get(start..end)
+    }
+}
+
+fn read_i32(data: &[u8], offset: usize) -> anyhow::Result<i32> {
+    let bytes = data
+        .get(offset..offset + 4)
+        .with_context(|| format!("{offset:#x} is outside guest memory"))?;
+    Ok(i32::from_le_bytes(bytes.try_into()?))
+}
+
 impl DemoRunner {
+    /// Throws away the guest's state and instantiates the module again, e.g.
+    /// after a trap left it in an unknown state. The last DPI passed to
+    /// `call_set_dpi` and the parameters set with `set_param` are passed to
+    /// the new instance too.
+    pub fn restart(&mut self) -> anyhow::Result<()> {
+        // The DPI may have changed while the guest was down, without a call
+        // to record.
+        self.record(Event::Restart { dpi: self.dpi });
+        // Replaying the restart sets the DPI and parameters again by itself.
+        let recording = self.recording.take();
+        let result = self.instantiate_again();
+        self.recording = recording;
+        result
+    }
+
+    fn instantiate_again(&mut self) -> anyhow::Result<()> {
+        let (mut store, instance, memory) = instantiate(&self.pre, &self.options)?;
+        // Spans recorded so far belong to the same run.
+        store.data_mut().spans = self.store.data_mut().spans.take();
+        store.data_mut().log = self.store.data_mut().log.take();
+        self.store = store;
+        self.instance = instance;
+        self.memory = memory;
+        self.framebuffer_size = None;
+        self.framebuffer = None;
+        self.invalidated = true;
+        self.frame = None;
+        self.damage.clear();
+        if let Some(dpi) = self.dpi {
+            self.call_set_dpi(dpi)?;
+        }
+        for (name, value) in self.params.clone() {
+            // A reloaded module may have dropped the parameter.
+            if let Some(index) = self.param_index(&name)? {
+                self.call_set_param(index, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Compiles the module again from the file it was loaded from and
+    /// restarts with it. The running instance is kept if that fails.
+    pub fn reload(&mut self) -> anyhow::Result<()> {
+        let path = self
+            .path
+            .as_ref()
+            .context("the module was not loaded from a file")?;
+        let bytes = std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        let (hash, module) = self.runtime.compile(&bytes)?;
+        let pre = self.runtime.link(&module, &self.options)?;
+        let previous = std::mem::replace(&mut self.pre, pre);
+        if let Err(error) = self.restart() {
+            self.pre = previous;
+            return Err(error);
+        }
+        if hash != self.hash {
+            // Reloading usually follows an edit; don't keep every version.
+            self.runtime.modules.lock().unwrap().remove(&self.hash);
+            self.hash = hash;
+        }
+        self.metadata = crate::abi::metadata(&bytes)?;
+        Ok(())
+    }
+
+    /// The module's exports and their types, like
+    /// `func(f64, i32, i32) -> i32`.
+    pub fn exports(&self) -> Vec<(String, String)> {
+        self.pre
+            .module()
+            .exports()
+            .map(|export| {
+                let ty = match export.ty() {
+                    ExternType::Func(func) => {
+                        let list = |types: &mut dyn Iterator<Item = ValType>| {
+                            types
+                                .map(|ty| ty.to_string())
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        };
+                        let params = list(&mut func.params());
+                        match func.results().len() {
+                            0 => format!("func({params})"),
+                            1 => format!("func({params}) -> {}", list(&mut func.results())),
+                            _ => format!("func({params}) -> ({})", list(&mut func.results())),
+                        }
+                    }
+                    ExternType::Global(global) => format!("global {}", global.content()),
+                    ExternType::Table(_) => "table".to_string(),
+                    ExternType::Memory(_) => "memory".to_string(),
+                };
+                (export.name().to_string(), ty)
+            })
+            .collect()
+    }
+
+    /// The current values of the guest's exported globals.
+    pub fn globals(&mut self) -> Vec<(String, String)> {
+        let names: Vec<String> = self
+            .pre
+            .module()
+            .exports()
+            .filter(|export| matches!(export.ty(), ExternType::Global(_)))
+            .map(|export| export.name().to_string())
+            .collect();
+        names
+            .into_iter()
+            .filter_map(|name| {
+                let value = match self
+                    .instance
+                    .get_global(&mut self.store, &name)?
+                    .get(&mut self.store)
+                {
+                    Val::I32(value) => value.to_string(),
+                    Val::I64(value) => value.to_string(),
+                    Val::F32(bits) => f32::from_bits(bits).to_string(),
+                    Val::F64(bits) => f64::from_bits(bits).to_string(),
+                    Val::V128(value) => format!("{value:#034x}"),
+                    Val::FuncRef(_) | Val::ExternRef(_) => "ref".to_string(),
+                };
+                Some((name, value))
+            })
+            .collect()
+    }
+
+    /// The size of the guest's memory in bytes.
+    pub fn memory_size(&self) -> usize {
+        self.memory.data_size(&self.store)
+    }
+
+    /// `len` bytes of guest memory from `address`.
+    pub fn read_memory(&self, address: usize, len: usize) -> anyhow::Result<&[u8]> {
+        self.memory
+            .data(&self.store)
+            .get(address..address.saturating_add(len))
+            .with_context(|| format!("{len} bytes at {address:#x} are outside guest memory"))
+    }
+
+    /// Where in guest memory the last frame is.
+    pub fn frame_address(&self) -> Option<usize> {
+        self.frame.as_ref().map(|(ptr, _)| *ptr)
+    }
+
+    /// Starts recording every call into the guest, for `replay::play`.
+    /// The recording begins with the DPI and parameters set so far, so it
+    /// is best started before anything else happens to the runner.
+    pub fn start_recording(&mut self) {
+        let mut events = Vec::new();
+        if let Some(dpi) = self.dpi {
+            events.push(Event::Dpi { dpi });
+        }
+        for (name, value) in &self.params {
+            events.push(Event::Param {
+                name: name.clone(),
+                value: *value,
+            });
+        }
+        self.recording = Some(events);
+    }
+
+    /// Stops recording and returns what was recorded, if anything was.
+    pub fn take_recording(&mut self) -> Option<Replay> {
+        let module = self.hash.iter().map(|byte| format!("{byte:02x}")).collect();
+        Some(Replay::new(module, self.recording.take()?))
+    }
+
+    fn record(&mut self, event: Event) {
+        if let Some(recording) = &mut self.recording {
+            recording.push(event);
+        }
+    }
+
+    /// The file the module was loaded from.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// The `key = value` pairs in the module's `demo` custom section, such
+    /// as its title and author.
+    pub fn metadata(&self) -> &BTreeMap<String, String> {
+        &self.metadata
+    }
+
+    /// Passes guest output and host messages about the guest to `handler`.
+    pub fn set_log_handler(&mut self, handler: impl Fn(&str) + Send + Sync + 'static) {
+        self.store.data_mut().log = Some(Arc::new(handler));
+    }
+
+    /// Prints `message` and passes it to the log handler.
+    pub fn log(&self, message: &str) {
+        self.store.data().log(message);
+    }
+
+    /// The guest's parameters and their current values. Guests list them
+    /// through optional exports: `param_name(i) -> i32` returns a pointer to
+    /// the name of parameter `i` as a C string, or 0 past the last one, and
+    /// `get_param(i) -> f64` and `set_param(i, f64)` access its value.
+    pub fn params(&mut self) -> anyhow::Result<Vec<Param>> {
+        let mut params = Vec::new();
+        let (Ok(param_name), Ok(get_param)) = (
+            self.instance
+                .get_typed_func::<i32, i32>(&mut self.store, "param_name"),
+            self.instance
+                .get_typed_func::<i32, f64>(&mut self.store, "get_param"),
+        ) else {
+            return Ok(params);
+        };
+        for index in 0.. {
+            let ptr = param_name.call(&mut self.store, index)?;
+            if ptr == 0 {
+                break;
+            }
+            let bytes = self
+                .memory
+                .data(&self.store)
+                .get(ptr as u32 as usize..)
+                .with_context(|| format!("{ptr:#x} is outside guest memory"))?;
+            let name = CStr::from_bytes_until_nul(bytes)?
+                .to_string_lossy()
+                .to_string();
+            let value = get_param.call(&mut self.store, index)?;
+            params.push(Param { name, value });
+        }
+        Ok(params)
+    }
+
+    /// Sets a parameter by name. The value is kept across restarts.
+    pub fn set_param(&mut self, name: &str, value: f64) -> anyhow::Result<()> {
+        let index = self
+            .param_index(name)?
+            .with_context(|| format!("the demo has no parameter {name:?}"))?;
+        self.call_set_param(index, value)?;
+        // Only sets that took are worth replaying.
+        self.record(Event::Param {
+            name: name.to_string(),
+            value,
+        });
+        self.params.insert(name.to_string(), value);
+        self.invalidated = true;
+        Ok(())
+    }
+
+    fn param_index(&mut self, name: &str) -> anyhow::Result<Option<i32>> {
+        let params = self.params()?;
+        Ok(params
+            .iter()
+            .position(|param| param.name == name)
+            .map(|index| index as i32))
+    }
+
+    fn call_set_param(&mut self, index: i32, value: f64) -> anyhow::Result<()> {
+        let set_param = self
+            .instance
+            .get_typed_func::<(i32, f64), ()>(&mut self.store, "set_param")?;
+        set_param.call(&mut self.store, (index, value))
+    }
+
+    /// Passes user input to the guest.
+    ///
+    /// ```
+    /// # fn main() -> anyhow::Result<()> {
+    /// # let path = std::env::temp_dir().join("demo-doc-input.wat");
+    /// # std::fs::write(&path, r#"(module
+    /// #     (memory (export "memory") 1)
+    /// #     (func (export "render") (param f64 i32 i32) (result i32) i32.const 0))"#)?;
+    /// use demo::plugin::{self, Input};
+    ///
+    /// let mut runner = plugin::create_file(&path)?;
+    /// // This guest has no `pointer_move` export, so the input is dropped.
+    /// runner.send_input(&Input::PointerMove { x: 10, y: 20 })?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn send_input(&mut self, input: &Input) -> anyhow::Result<()> {
+        self.record(Event::from(*input));
+        let (name, args) = match *input {
+            Input::PointerMove { x, y } => ("pointer_move", (x, y)),
+            Input::PointerButton { button, pressed } => {
+                ("pointer_button", (button, pressed as i32))
+            }
+            Input::Key { code, pressed } => ("key", (code, pressed as i32)),
+        };
+        if let Ok(func) = self
+            .instance
+            .get_typed_func::<(i32, i32), ()>(&mut self.store, name)
+        {
+            func.call(&mut self.store, args)?;
+        }
+        Ok(())
+    }
+
+    /// Tells the guest the display DPI, at startup and whenever it changes.
+    /// Guests answer through an optional `set_dpi(dpi) -> i32` export with 0
+    /// for physical and 1 for logical resolution; without it they get
+    /// physical resolution.
+    pub fn call_set_dpi(&mut self, dpi: i32) -> anyhow::Result<Resolution> {
+        self.record(Event::Dpi { dpi });
+        self.dpi = Some(dpi);
+        self.resolution = if let Ok(set_dpi) = self
+            .instance
+            .get_typed_func::<i32, i32>(&mut self.store, "set_dpi")
+        {
+            match set_dpi.call(&mut self.store, dpi)? {
+                0 => Resolution::Physical,
+                1 => Resolution::Logical,
+                other => anyhow::bail!("set_dpi returned unknown resolution {other}"),
+            }
+        } else {
+            Resolution::Physical
+        };
+        Ok(self.resolution)
+    }
+
+    /// The resolution the guest asked for the last time `call_set_dpi`
+    /// succeeded.
+    pub fn resolution(&self) -> Resolution {
+        self.resolution
+    }
+
+    /// Changes the DPI passed to the guest when it restarts, without telling
+    /// the running instance, e.g. because it failed.
+    pub fn set_dpi_on_restart(&mut self, dpi: i32) {
+        self.dpi = Some(dpi);
+    }
+
+    /// Lets the guest pick its size. All sizes are in logical pixels.
     pub fn call_set_dimensions(
         &mut self,
         dpi: i32,
@@ -72,6 +978,12 @@ impl DemoRunner {
         preferred: &Rect,
         max: &Rect,
     ) -> anyhow::Result<Rect> {
+        self.record(Event::Dimensions {
+            dpi,
+            min: [min.width, min.height],
+            preferred: [preferred.width, preferred.height],
+            max: [max.width, max.height],
+        });
         if let Ok(set_dimensions) = self
             .instance
             .get_typed_func::<(i32, i32, i32, i32, i32, i32, i32), (i32, i32)>(
@@ -98,23 +1010,479 @@ impl DemoRunner {
         }
     }
 
+    /// Asks the guest to (re)allocate its framebuffer for `size`. The guest
+    /// returns the address the next frame is drawn into, which `render` must
+    /// then report, or 0 or less if it can't allocate one. Guests that don't
+    /// export `resize_framebuffer` manage their buffer on their own and only
+    /// report it as the result of `render`.
+    pub fn call_resize_framebuffer(&mut self, size: &Rect) -> anyhow::Result<()> {
+        self.framebuffer_size = None;
+        self.framebuffer = None;
+        if let Ok(resize_framebuffer) = self
+            .instance
+            .get_typed_func::<(i32, i32), i32>(&mut self.store, "resize_framebuffer")
+        {
+            let ptr = resize_framebuffer.call(&mut self.store, (size.width, size.height))?;
+            anyhow::ensure!(
+                ptr > 0,
+                "the guest couldn't allocate a {}x{} framebuffer",
+                size.width,
+                size.height
+            );
+            self.framebuffer = Some(ptr as usize);
+        }
+        self.framebuffer_size = Some(size.clone());
+        self.invalidated = true;
+        Ok(())
+    }
+
+    /// Makes the next `call_render` report the whole frame as damaged, e.g.
+    /// after the host lost the contents of its copy of the frame.
+    pub fn invalidate(&mut self) {
+        self.invalidated = true;
+    }
+
+    /// Reads the regions the guest changed in the frame it just rendered.
+    /// Guests report them through an optional `damage` export returning a
+    /// pointer to an `i32` count followed by that many `x, y, width, height`
+    /// tuples. A negative count, or no export at all, means the whole frame.
+    fn call_damage(&mut self, size: &Rect) -> anyhow::Result<Vec<Region>> {
+        if std::mem::take(&mut self.invalidated) {
+            return Ok(vec![Region::full(size)]);
+        }
+        let Ok(damage) = self
+            .instance
+            .get_typed_func::<(), i32>(&mut self.store, "damage")
+        else {
+            return Ok(vec![Region::full(size)]);
+        };
+        let ptr = damage.call(&mut self.store, ())? as u32 as usize;
+
+        let data = self.memory.data(&self.store);
+        let count = read_i32(data, ptr)?;
+        if count < 0 {
+            return Ok(vec![Region::full(size)]);
+        }
+        let mut regions = Vec::new();
+        for i in 0..count as usize {
+            let offset = ptr + 4 + i * 16;
+            let region = Region {
+                x: read_i32(data, offset)?,
+                y: read_i32(data, offset + 4)?,
+                width: read_i32(data, offset + 8)?,
+                height: read_i32(data, offset + 12)?,
+            };
+            regions.extend(region.clip(size));
+        }
+        Ok(regions)
+    }
+
+    /// Renders a frame and returns a view of it in guest memory.
+    pub fn render_frame(&mut self, time: f64, size: &Rect) -> anyhow::Result<FrameView<'_>> {
+        self.record(Event::Frame {
+            time,
+            size: [size.width, size.height],
+            sha1: None,
+        });
+        if self.framebuffer_size.as_ref() != Some(size) {
+            self.call_resize_framebuffer(size)?;
+        }
+
+        let run = self
+            .instance
+            .get_typed_func::<(f64, i32, i32), i32>(&mut self.store, "render")?;
+
+        self.frame = None;
+        let ptr = run.call(&mut self.store, (time, size.width, size.height))? as u32 as usize;
+        if let Some(spans) = &mut self.store.data_mut().spans {
+            spans.end_frame();
+        }
+        // Only the first frame is drawn where `resize_framebuffer` said;
+        // double-buffering guests alternate after that.
+        if let Some(framebuffer) = self.framebuffer.take() {
+            anyhow::ensure!(
+                ptr == framebuffer,
+                "the guest rendered into {ptr:#x}, not the framebuffer it allocated at \
+                 {framebuffer:#x}"
+            );
+        }
+        self.damage = self.call_damage(size)?;
+        self.frame = Some((ptr, size.clone()));
+        if self.recording.is_some() {
+            let hash = replay::frame_hash(&self.frame()?);
+            if let Some(Event::Frame { sha1, .. }) =
+                self.recording.as_mut().and_then(|events| events.last_mut())
+            {
+                *sha1 = Some(hash);
+            }
+        }
+        self.frame()
+    }
+
+    /// Where the guest spent its time during the last frame, if span
+    /// profiling is enabled.
+    pub fn frame_profile(&self) -> Option<&FrameProfile> {
+        self.store
+            .data()
+            .spans
+            .as_ref()
+            .map(|spans| spans.last_frame())
+    }
+
+    /// Writes every span recorded so far as a Chrome trace; needs
+    /// `Options::trace`.
+    pub fn write_chrome_trace(&self, out: impl std::io::Write) -> anyhow::Result<()> {
+        let spans = self
+            .store
+            .data()
+            .spans
+            .as_ref()
+            .filter(|_| self.options.trace)
+            .context("span tracing is not enabled")?;
+        if spans.dropped() > 0 {
+            self.log(&format!(
+                "the trace only has the first {} spans; {} more were left out",
+                crate::profile::TRACE_LIMIT,
+                spans.dropped()
+            ));
+        }
+        Ok(spans.write_chrome_trace(out)?)
+    }
+
+    /// A view of the last frame rendered by `render_frame` or `call_render`.
+    pub fn frame(&self) -> anyhow::Result<FrameView<'_>> {
+        let (ptr, size) = self.frame.as_ref().context("no frame rendered yet")?;
+        let format = PixelFormat::Rgba8;
+        let (Ok(width), Ok(height)) = (usize::try_from(size.width), usize::try_from(size.height))
+        else {
+            anyhow::bail!("a frame can't be {}x{}", size.width, size.height);
+        };
+        let stride = width
+            .checked_mul(format.bytes_per_pixel())
+            .context("the frame is too wide")?;
+        let len = stride.checked_mul(height).context("the frame is too big")?;
+        let data = self
+            .memory
+            .data(&self.store)
+            .get(*ptr..ptr.saturating_add(len))
+            .with_context(|| format!("frame at {ptr:#x} ({len} bytes) is outside guest memory"))?;
+        Ok(FrameView {
+            data,
+            size: size.clone(),
+            stride,
+            format,
+            damage: &self.damage,
+        })
+    }
+
+    /// Renders a frame and hands each damaged region to `handle_data`, which
+    /// is not called at all when nothing changed. The slice starts at the
+    /// region's top-left pixel and rows are `size.width * 4` bytes apart.
     pub fn call_render(
         &mut self,
         time: f64,
         size: &Rect,
-        handle_data: impl Fn(&[u8]) -> anyhow::Result<()>,
+        mut handle_data: impl FnMut(&Region, &[u8]) -> anyhow::Result<()>,
     ) -> anyhow::Result<()> {
-        let run = self
-            .instance
-            .get_typed_func::<(f64, i32, i32), i32>(&mut self.store, "render")?;
+        let frame = self.render_frame(time, size)?;
+        for region in frame.damage {
+            let data = frame
+                .region_data(region)
+                .context("damage outside the frame")?;
+            handle_data(region, data)?;
+        }
+        Ok(())
+    }
+}
 
-        let ptr = run.call(&mut self.store, (time, size.width, size.height))? as usize;
-        let len = size.width as usize * size.height as usize * 4;
+#[cfg(test)]
+mod test {
+    use super::*;
 
-        let memory = self
-            .instance
-            .get_memory(&mut self.store, "memory")
-            .context("no memory")?;
-        handle_data(&memory.data(&mut self.store)[ptr..(ptr + len)])
+    #[test]
+    fn test_policy() {
+        let options = Options {
+            policy: Policy::allow([Capability::Clock]),
+            ..Default::default()
+        };
+        let error = create_wat_with_options(
+            r#"(module
+                (import "env" "output" (func (param i32)))
+                (import "env" "clock" (func (result f64)))
+                (memory (export "memory") 1)
+                (func (export "render") (param f64 i32 i32) (result i32) i32.const 0))"#,
+            &options,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "refusing to link the demo: it imports env.output, which needs the `logging` \
+             capability; it imports env.clock, which the host doesn't provide; it may only use \
+             clock"
+        );
+    }
+
+    #[test]
+    fn test_runtime() {
+        let guest = br#"(module
+            (memory (export "memory") 1)
+            (func (export "render") (param f64 i32 i32) (result i32) i32.const 0))"#;
+        let runtime = DemoRuntime::new(&RuntimeOptions {
+            pool_size: Some(2),
+            ..Default::default()
+        })
+        .unwrap();
+        let options = Options::default();
+        let mut first = runtime.create_bytes(guest, &options).unwrap();
+        let second = runtime.create_bytes(guest, &options).unwrap();
+        assert_eq!(runtime.modules.lock().unwrap().len(), 1);
+        // Both slots of the pool are taken.
+        assert!(runtime.create_bytes(guest, &options).is_err());
+        assert!(first.restart().is_err());
+        drop(second);
+        first.restart().unwrap();
+        let size = Rect {
+            width: 2,
+            height: 2,
+        };
+        assert_eq!(first.render_frame(0.0, &size).unwrap().data, [0; 16]);
+        let shared = DemoRuntime::shared(&RuntimeOptions::default()).unwrap();
+        let again = DemoRuntime::shared(&RuntimeOptions::default()).unwrap();
+        assert!(Arc::ptr_eq(&shared.modules, &again.modules));
+    }
+
+    #[test]
+    fn test_set_dimensions() {
+        let (min, preferred, max) = (
+            Rect {
+                width: 10,
+                height: 20,
+            },
+            Rect {
+                width: 30,
+                height: 40,
+            },
+            Rect {
+                width: 50,
+                height: 60,
+            },
+        );
+        let mut plain = create_bytes(
+            br#"(module
+                (memory (export "memory") 1)
+                (func (export "render") (param f64 i32 i32) (result i32) i32.const 0))"#,
+        )
+        .unwrap();
+        assert_eq!(
+            plain
+                .call_set_dimensions(96, &min, &preferred, &max)
+                .unwrap(),
+            preferred
+        );
+
+        // Picks the maximum width and, to check the arguments, the DPI as
+        // the height.
+        let mut picky = create_wat(
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "set_dimensions")
+                    (param i32 i32 i32 i32 i32 i32 i32) (result i32 i32)
+                    local.get 5
+                    local.get 0)
+                (func (export "render") (param f64 i32 i32) (result i32) i32.const 0))"#,
+        )
+        .unwrap();
+        assert_eq!(
+            picky
+                .call_set_dimensions(96, &min, &preferred, &max)
+                .unwrap(),
+            Rect {
+                width: 50,
+                height: 96
+            }
+        );
+
+        let mut broken = create_wat(
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "set_dimensions")
+                    (param i32 i32 i32 i32 i32 i32 i32) (result i32 i32)
+                    unreachable)
+                (func (export "render") (param f64 i32 i32) (result i32) i32.const 0))"#,
+        )
+        .unwrap();
+        assert!(broken
+            .call_set_dimensions(96, &min, &preferred, &max)
+            .is_err());
+    }
+
+    fn damage(runner: &mut DemoRunner, size: &Rect) -> Vec<Region> {
+        let mut regions = Vec::new();
+        runner
+            .call_render(0.0, size, |region, data| {
+                let bytes = (region.height as usize - 1) * size.width as usize * 4
+                    + region.width as usize * 4;
+                assert!(data.len() >= bytes);
+                regions.push(region.clone());
+                Ok(())
+            })
+            .unwrap();
+        regions
+    }
+
+    #[test]
+    fn test_render() {
+        let size = Rect {
+            width: 4,
+            height: 4,
+        };
+
+        // Without a `damage` export every frame is damaged in full.
+        let mut plain = create_wat(
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "render") (param f64 i32 i32) (result i32) i32.const 0))"#,
+        )
+        .unwrap();
+        assert_eq!(damage(&mut plain, &size), [Region::full(&size)]);
+        assert_eq!(damage(&mut plain, &size), [Region::full(&size)]);
+
+        // Reports one region sticking out of the frame and one outside it.
+        let mut partial = create_wat(
+            r#"(module
+                (memory (export "memory") 1)
+                (data (i32.const 1024)
+                    "\02\00\00\00"
+                    "\01\00\00\00\02\00\00\00\0a\00\00\00\0a\00\00\00"
+                    "\08\00\00\00\00\00\00\00\01\00\00\00\01\00\00\00")
+                (func (export "render") (param f64 i32 i32) (result i32) i32.const 0)
+                (func (export "damage") (result i32) i32.const 1024))"#,
+        )
+        .unwrap();
+        // The first frame at a size is always damaged in full.
+        assert_eq!(damage(&mut partial, &size), [Region::full(&size)]);
+        let clipped = Region {
+            x: 1,
+            y: 2,
+            width: 3,
+            height: 2,
+        };
+        assert_eq!(damage(&mut partial, &size), std::slice::from_ref(&clipped));
+        partial.invalidate();
+        assert_eq!(damage(&mut partial, &size), [Region::full(&size)]);
+        assert_eq!(damage(&mut partial, &size), [clipped]);
+        let bigger = Rect {
+            width: 8,
+            height: 8,
+        };
+        assert_eq!(damage(&mut partial, &bigger), [Region::full(&bigger)]);
+
+        // A negative count means the whole frame.
+        let mut everything = create_wat(
+            r#"(module
+                (memory (export "memory") 1)
+                (data (i32.const 1024) "\ff\ff\ff\ff")
+                (func (export "render") (param f64 i32 i32) (result i32) i32.const 0)
+                (func (export "damage") (result i32) i32.const 1024))"#,
+        )
+        .unwrap();
+        damage(&mut everything, &size);
+        assert_eq!(damage(&mut everything, &size), [Region::full(&size)]);
+
+        let mut outside = create_wat(
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "render") (param f64 i32 i32) (result i32) i32.const 65530))"#,
+        )
+        .unwrap();
+        let error = outside.render_frame(0.0, &size).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "frame at 0xfffa (64 bytes) is outside guest memory"
+        );
+
+        let mut view = create_wat(
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "render") (param f64 i32 i32) (result i32) i32.const 0))"#,
+        )
+        .unwrap();
+        let frame = view.render_frame(0.0, &size).unwrap();
+        let inner = Region {
+            x: 1,
+            y: 1,
+            width: 2,
+            height: 3,
+        };
+        assert_eq!(frame.region_data(&inner).unwrap().len(), 2 * 16 + 2 * 4);
+        for region in [
+            Region {
+                width: 0,
+                ..inner.clone()
+            },
+            Region {
+                height: -1,
+                ..inner.clone()
+            },
+            Region {
+                x: -1,
+                ..inner.clone()
+            },
+            Region {
+                y: 2,
+                ..inner.clone()
+            },
+            Region {
+                x: i32::MAX,
+                ..inner.clone()
+            },
+        ] {
+            assert_eq!(frame.region_data(&region), None, "{region:?}");
+        }
+        let negative = Rect {
+            width: -4,
+            height: 4,
+        };
+        let error = view.render_frame(0.0, &negative).err().unwrap();
+        assert_eq!(error.to_string(), "a frame can't be -4x4");
+
+        let mut trapping = create_wat(
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "render") (param f64 i32 i32) (result i32) unreachable))"#,
+        )
+        .unwrap();
+        assert!(trapping.render_frame(0.0, &size).is_err());
+        assert!(trapping.frame().is_err());
+
+        let allocating = r#"(module
+            (memory (export "memory") 1)
+            (func (export "resize_framebuffer") (param i32 i32) (result i32) i32.const 256)
+            (func (export "render") (param f64 i32 i32) (result i32) i32.const 256))"#;
+        let mut allocated = create_wat(allocating).unwrap();
+        allocated.render_frame(0.0, &size).unwrap();
+        let mut full = create_wat(&allocating.replace(
+            "(result i32) i32.const 256)\n",
+            "(result i32) i32.const 0)\n",
+        ))
+        .unwrap();
+        let error = full.render_frame(0.0, &size).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "the guest couldn't allocate a 4x4 framebuffer"
+        );
+        let mut elsewhere =
+            create_wat(&allocating.replace("i32) i32.const 256))", "i32) i32.const 512))"))
+                .unwrap();
+        let error = elsewhere.render_frame(0.0, &size).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "the guest rendered into 0x200, not the framebuffer it allocated at 0x100"
+        );
+
+        let mut silent = create_wat(r#"(module (memory (export "memory") 1))"#).unwrap();
+        assert!(silent.render_frame(0.0, &size).is_err());
+        assert!(silent.reload().is_err());
     }
 }
@@ -0,0 +1,437 @@
+use crate::config::Color;
+use crate::plugin::{self, Rect};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+
+/// The most a `scale` pass may resize frames by.
+pub const MAX_FACTOR: f64 = 8.0;
+
+/// The biggest frame passes may make: the largest one guests are offered,
+/// at four times the base DPI.
+pub const MAX_OUTPUT: Rect = Rect {
+    width: plugin::MAX_SIZE.width * 4,
+    height: plugin::MAX_SIZE.height * 4,
+};
+
+/// RGBA pixels, a byte per channel, in rows from the top without padding.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Image {
+    pub data: Vec<u8>,
+    pub size: Rect,
+}
+
+impl Image {
+    /// Copies `size` pixels whose rows start `stride` bytes apart in `data`.
+    pub fn from_rows(data: &[u8], size: &Rect, stride: usize) -> Image {
+        let row = size.width as usize * 4;
+        let mut image = Vec::with_capacity(row * size.height as usize);
+        for y in 0..size.height as usize {
+            image.extend_from_slice(&data[y * stride..][..row]);
+        }
+        Image {
+            data: image,
+            size: size.clone(),
+        }
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> &[u8] {
+        let start = (y * self.size.width as usize + x) * 4;
+        &self.data[start..start + 4]
+    }
+
+    /// The colour at `x, y` in pixels, blending the four pixels around it.
+    fn sample(&self, x: f64, y: f64) -> [u8; 4] {
+        let max_x = self.size.width as usize - 1;
+        let max_y = self.size.height as usize - 1;
+        let (x, y) = (x.max(0.0), y.max(0.0));
+        let (left, top) = ((x as usize).min(max_x), (y as usize).min(max_y));
+        let (right, bottom) = ((left + 1).min(max_x), (top + 1).min(max_y));
+        let (fx, fy) = (x - left as f64, y - top as f64);
+        let mut out = [0; 4];
+        for (c, out) in out.iter_mut().enumerate() {
+            let at = |x, y| self.pixel(x, y)[c] as f64;
+            let upper = at(left, top) * (1.0 - fx) + at(right, top) * fx;
+            let lower = at(left, bottom) * (1.0 - fx) + at(right, bottom) * fx;
+            *out = (upper * (1.0 - fy) + lower * fy).round() as u8;
+        }
+        out
+    }
+}
+
+/// How to rescale frames.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum Filter {
+    /// Blocky; keeps pixel art sharp.
+    #[default]
+    Nearest,
+    Bilinear,
+    /// Sharper than bilinear, with some ringing around edges.
+    Lanczos,
+}
+
+impl Filter {
+    /// How far the filter reaches, in source pixels when upscaling.
+    fn radius(&self) -> f64 {
+        match self {
+            Filter::Nearest => 0.5,
+            Filter::Bilinear => 1.0,
+            Filter::Lanczos => 3.0,
+        }
+    }
+
+    fn weight(&self, x: f64) -> f64 {
+        let sinc = |x: f64| {
+            if x == 0.0 {
+                1.0
+            } else {
+                let x = x * std::f64::consts::PI;
+                x.sin() / x
+            }
+        };
+        match self {
+            Filter::Nearest => (x.abs() <= 0.5) as i32 as f64,
+            Filter::Bilinear => (1.0 - x.abs()).max(0.0),
+            Filter::Lanczos if x.abs() < 3.0 => sinc(x) * sinc(x / 3.0),
+            Filter::Lanczos => 0.0,
+        }
+    }
+}
+
+/// A step in turning a rendered frame into what is shown. Config files list
+/// them as tables with the name of the pass under `pass`, e.g.
+///
+/// ```toml
+/// [[demo.sdf.postprocess]]
+/// pass = "scale"
+/// factor = 2
+/// filter = "lanczos"
+/// ```
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(tag = "pass", rename_all = "lowercase", deny_unknown_fields)]
+#[non_exhaustive]
+pub enum Pass {
+    /// Resizes the frame by `factor`, above 0 and up to `MAX_FACTOR`.
+    Scale {
+        #[serde(deserialize_with = "factor")]
+        factor: f64,
+        #[serde(default)]
+        filter: Filter,
+    },
+    /// Raises each channel to `1 / gamma`, so values above 1 brighten the
+    /// midtones.
+    Gamma { gamma: f64 },
+    /// Multiplies each channel by `brightness`.
+    Brightness { brightness: f64 },
+    /// Bends the frame like the glass of a CRT; 0 is flat.
+    Crt { curvature: f64 },
+    /// Darkens every other row by `intensity`, from 0 to 1.
+    Scanlines { intensity: f64 },
+    /// Replaces each pixel with the nearest of `colors`.
+    Palette { colors: Vec<Color> },
+}
+
+impl Pass {
+    /// Runs the pass over `image`. Only scaling fails, where the frame
+    /// would get bigger than `MAX_OUTPUT`.
+    pub fn apply(&self, image: Image) -> anyhow::Result<Image> {
+        Ok(match self {
+            Pass::Scale { factor, filter } => {
+                let scaled = |length: i32| (length as f64 * factor).round().max(1.0);
+                let (width, height) = (scaled(image.size.width), scaled(image.size.height));
+                anyhow::ensure!(
+                    factor.is_finite()
+                        && width <= MAX_OUTPUT.width as f64
+                        && height <= MAX_OUTPUT.height as f64,
+                    "scaling a {}x{} frame by {factor} would make it bigger than {}x{}",
+                    image.size.width,
+                    image.size.height,
+                    MAX_OUTPUT.width,
+                    MAX_OUTPUT.height
+                );
+                let size = Rect {
+                    width: width as i32,
+                    height: height as i32,
+                };
+                resize(&image, &size, *filter)
+            }
+            Pass::Gamma { gamma } => map_channels(image, |v| v.powf(1.0 / gamma)),
+            Pass::Brightness { brightness } => map_channels(image, |v| v * brightness),
+            Pass::Crt { curvature } => crt(&image, *curvature),
+            Pass::Scanlines { intensity } => {
+                let mut image = image;
+                let row = image.size.width as usize * 4;
+                let keep = (1.0 - intensity).clamp(0.0, 1.0);
+                for line in image.data.chunks_exact_mut(row).skip(1).step_by(2) {
+                    for pixel in line.chunks_exact_mut(4) {
+                        for channel in &mut pixel[..3] {
+                            *channel = (*channel as f64 * keep).round() as u8;
+                        }
+                    }
+                }
+                image
+            }
+            Pass::Palette { colors } => palette(image, colors),
+        })
+    }
+}
+
+fn factor<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    let factor = f64::deserialize(deserializer)?;
+    if factor.is_finite() && factor > 0.0 && factor <= MAX_FACTOR {
+        Ok(factor)
+    } else {
+        Err(D::Error::custom(format!(
+            "factor: expected a number above 0 and up to {MAX_FACTOR}, got {factor}"
+        )))
+    }
+}
+
+/// Runs `passes` over `image` in order.
+pub fn apply(passes: &[Pass], image: Image) -> anyhow::Result<Image> {
+    passes
+        .iter()
+        .try_fold(image, |image, pass| pass.apply(image))
+}
+
+/// Scales `image` to `size`.
+pub fn resize(image: &Image, size: &Rect, filter: Filter) -> Image {
+    if image.size == *size {
+        return image.clone();
+    }
+    let (width, height) = (image.size.width as usize, image.size.height as usize);
+    let (new_width, new_height) = (size.width as usize, size.height as usize);
+    // Separably: first each row, then each column of the result.
+    let columns = weights(width, new_width, filter);
+    let mut wide = vec![0.0; new_width * height * 4];
+    for y in 0..height {
+        for (x, taps) in columns.iter().enumerate() {
+            for &(from, weight) in taps {
+                for c in 0..4 {
+                    wide[(y * new_width + x) * 4 + c] += image.pixel(from, y)[c] as f64 * weight;
+                }
+            }
+        }
+    }
+    let rows = weights(height, new_height, filter);
+    let mut data = vec![0; new_width * new_height * 4];
+    for (y, taps) in rows.iter().enumerate() {
+        for x in 0..new_width {
+            for c in 0..4 {
+                let value: f64 = taps
+                    .iter()
+                    .map(|&(from, weight)| wide[(from * new_width + x) * 4 + c] * weight)
+                    .sum();
+                data[(y * new_width + x) * 4 + c] = value.round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+    Image {
+        data,
+        size: size.clone(),
+    }
+}
+
+/// For each pixel along an axis scaled from `from` to `to` pixels, the source
+/// pixels it is made of and their weights, which add up to 1.
+fn weights(from: usize, to: usize, filter: Filter) -> Vec<Vec<(usize, f64)>> {
+    let scale = from as f64 / to as f64;
+    // Downscaling widens the filter to cover every source pixel.
+    let stretch = scale.max(1.0);
+    let radius = filter.radius() * stretch;
+    (0..to)
+        .map(|i| {
+            let center = (i as f64 + 0.5) * scale;
+            let first = (center - radius).floor().max(0.0) as usize;
+            let last = ((center + radius).ceil() as usize).min(from);
+            let mut taps: Vec<(usize, f64)> = (first..last)
+                .map(|j| (j, filter.weight((j as f64 + 0.5 - center) / stretch)))
+                .filter(|(_, weight)| *weight != 0.0)
+                .collect();
+            let total: f64 = taps.iter().map(|(_, weight)| weight).sum();
+            if total == 0.0 {
+                return vec![((center as usize).min(from - 1), 1.0)];
+            }
+            for (_, weight) in &mut taps {
+                *weight /= total;
+            }
+            taps
+        })
+        .collect()
+}
+
+/// Applies `f` to the colour channels as values from 0 to 1.
+fn map_channels(mut image: Image, f: impl Fn(f64) -> f64) -> Image {
+    let table: Vec<u8> = (0..=255)
+        .map(|v| (f(v as f64 / 255.0) * 255.0).round().clamp(0.0, 255.0) as u8)
+        .collect();
+    for pixel in image.data.chunks_exact_mut(4) {
+        for channel in &mut pixel[..3] {
+            *channel = table[*channel as usize];
+        }
+    }
+    image
+}
+
+fn crt(image: &Image, curvature: f64) -> Image {
+    let (width, height) = (image.size.width as f64, image.size.height as f64);
+    let mut data = Vec::with_capacity(image.data.len());
+    for y in 0..image.size.height {
+        for x in 0..image.size.width {
+            // From -1 to 1 across the frame, bulging out towards the corners.
+            let u = (x as f64 + 0.5) / width * 2.0 - 1.0;
+            let v = (y as f64 + 0.5) / height * 2.0 - 1.0;
+            let bend = 1.0 + curvature * (u * u + v * v);
+            let (u, v) = (u * bend, v * bend);
+            if u.abs() > 1.0 || v.abs() > 1.0 {
+                data.extend_from_slice(&[0, 0, 0, 255]);
+            } else {
+                let x = (u + 1.0) / 2.0 * width - 0.5;
+                let y = (v + 1.0) / 2.0 * height - 0.5;
+                data.extend_from_slice(&image.sample(x, y));
+            }
+        }
+    }
+    Image {
+        data,
+        size: image.size.clone(),
+    }
+}
+
+fn palette(mut image: Image, colors: &[Color]) -> Image {
+    if colors.is_empty() {
+        return image;
+    }
+    let colors: Vec<[i32; 3]> = colors
+        .iter()
+        .map(|color| [color.r, color.g, color.b].map(|c| (c * 255.0).round() as i32))
+        .collect();
+    for pixel in image.data.chunks_exact_mut(4) {
+        let distance = |color: &&[i32; 3]| -> i32 {
+            (0..3).map(|c| (color[c] - pixel[c] as i32).pow(2)).sum()
+        };
+        let nearest = colors.iter().min_by_key(distance).unwrap();
+        for c in 0..3 {
+            pixel[c] = nearest[c] as u8;
+        }
+    }
+    image
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn image(width: i32, height: i32, data: &[u8]) -> Image {
+        Image {
+            data: data.to_vec(),
+            size: Rect { width, height },
+        }
+    }
+
+    #[test]
+    fn test_postprocess() {
+        let red_blue = image(2, 1, &[255, 0, 0, 255, 0, 0, 255, 255]);
+        let double = |filter| Pass::Scale {
+            factor: 2.0,
+            filter,
+        };
+        assert_eq!(
+            double(Filter::Nearest)
+                .apply(red_blue.clone())
+                .unwrap()
+                .data,
+            [[255, 0, 0, 255]; 2]
+                .iter()
+                .chain(&[[0, 0, 255, 255]; 2])
+                .cycle()
+                .take(8)
+                .flatten()
+                .copied()
+                .collect::<Vec<u8>>()
+        );
+        // Filters keep flat colours flat, and blend in between.
+        let grey = image(3, 2, &[100; 24]);
+        for filter in [Filter::Bilinear, Filter::Lanczos] {
+            let up = double(filter).apply(grey.clone()).unwrap();
+            assert_eq!(
+                up.size,
+                Rect {
+                    width: 6,
+                    height: 4
+                }
+            );
+            assert!(up.data.iter().all(|v| *v == 100));
+            let down = resize(
+                &grey,
+                &Rect {
+                    width: 1,
+                    height: 1,
+                },
+                filter,
+            );
+            assert_eq!(down.data, [100; 4]);
+        }
+        let blended = double(Filter::Bilinear).apply(red_blue.clone()).unwrap();
+        assert_eq!(blended.data[4..8], [191, 0, 64, 255]);
+
+        let brighter = apply(
+            &[
+                Pass::Gamma { gamma: 2.0 },
+                Pass::Brightness { brightness: 2.0 },
+            ],
+            image(1, 1, &[64, 0, 255, 10]),
+        )
+        .unwrap();
+        assert_eq!(brighter.data, [255, 0, 255, 10]);
+
+        let lines = Pass::Scanlines { intensity: 0.5 }
+            .apply(image(1, 2, &[200; 8]))
+            .unwrap();
+        assert_eq!(lines.data, [200, 200, 200, 200, 100, 100, 100, 200]);
+
+        assert_eq!(
+            Pass::Crt { curvature: 0.0 }.apply(grey.clone()).unwrap(),
+            grey
+        );
+        let bent = Pass::Crt { curvature: 1.0 }.apply(grey.clone()).unwrap();
+        assert_eq!(bent.data[..4], [0, 0, 0, 255]);
+
+        let black_white = Pass::Palette {
+            colors: vec![
+                Color::WHITE,
+                Color {
+                    r: 0.0,
+                    g: 0.0,
+                    b: 0.0,
+                },
+            ],
+        };
+        assert_eq!(
+            black_white.apply(red_blue).unwrap().data,
+            [0, 0, 0, 255, 0, 0, 0, 255]
+        );
+        assert_eq!(
+            black_white.apply(grey.clone()).unwrap().data,
+            [0, 0, 0, 100].repeat(6)
+        );
+
+        // Scaling can't make frames bigger than any display shows.
+        let huge = Pass::Scale {
+            factor: 1e9,
+            filter: Filter::Nearest,
+        };
+        let error = huge.apply(grey.clone()).unwrap_err();
+        assert!(error.to_string().contains("would make it bigger than"));
+        let most = Pass::Scale {
+            factor: MAX_FACTOR,
+            filter: Filter::Nearest,
+        };
+        assert!(apply(&vec![most; 4], grey).is_err());
+        for factor in ["0", "-1", "nan", "1e9"] {
+            let toml = format!("pass = \"scale\"\nfactor = {factor}");
+            assert!(toml::from_str::<Pass>(&toml).is_err(), "{factor}");
+        }
+    }
+}
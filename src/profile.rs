@@ -0,0 +1,252 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+/// The most spans `SpanRecorder` keeps for a trace. Spans after that are
+/// still summarized per frame but left out of the trace.
+pub const TRACE_LIMIT: usize = 1 << 20;
+
+/// Collects the spans a guest marks with its `span_begin` and `span_end`
+/// imports.
+pub(crate) struct SpanRecorder {
+    origin: Instant,
+    /// Every span path seen so far, so that a span costs no allocations once
+    /// its path has been seen.
+    nodes: Vec<Node>,
+    /// The nodes of outermost spans.
+    roots: Vec<usize>,
+    /// Open spans, as indices into `nodes`.
+    stack: Vec<(usize, Instant)>,
+    /// Stats for this frame, by index into `nodes`.
+    current: Vec<SpanStats>,
+    last_frame: FrameProfile,
+    trace: Option<Vec<TraceEvent>>,
+    /// Spans left out of the trace because it reached `TRACE_LIMIT`.
+    dropped: u64,
+}
+
+/// A span and the spans it was seen in, outermost first.
+struct Node {
+    name: String,
+    parent: Option<usize>,
+    children: Vec<usize>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SpanStats {
+    pub calls: u64,
+    pub total: Duration,
+}
+
+/// Time spent in each span during one frame, keyed by the names of the span
+/// and its enclosing spans, outermost first.
+#[derive(Clone, Debug, Default)]
+pub struct FrameProfile {
+    pub spans: BTreeMap<Vec<String>, SpanStats>,
+}
+
+struct TraceEvent {
+    node: usize,
+    start: Duration,
+    duration: Duration,
+}
+
+impl SpanRecorder {
+    /// With `trace` set, every span is kept for `write_chrome_trace` as well as
+    /// summarized per frame.
+    pub fn new(trace: bool) -> SpanRecorder {
+        SpanRecorder {
+            origin: Instant::now(),
+            nodes: Vec::new(),
+            roots: Vec::new(),
+            stack: Vec::new(),
+            current: Vec::new(),
+            last_frame: FrameProfile::default(),
+            trace: trace.then(Vec::new),
+            dropped: 0,
+        }
+    }
+
+    pub fn begin(&mut self, name: &str) {
+        let parent = self.stack.last().map(|&(node, _)| node);
+        let siblings = match parent {
+            Some(parent) => &self.nodes[parent].children,
+            None => &self.roots,
+        };
+        let found = siblings
+            .iter()
+            .copied()
+            .find(|&node| self.nodes[node].name == name);
+        let node = found.unwrap_or_else(|| {
+            let node = self.nodes.len();
+            self.nodes.push(Node {
+                name: name.to_string(),
+                parent,
+                children: Vec::new(),
+            });
+            match parent {
+                Some(parent) => self.nodes[parent].children.push(node),
+                None => self.roots.push(node),
+            }
+            node
+        });
+        self.stack.push((node, Instant::now()));
+    }
+
+    pub fn end(&mut self) {
+        let end = Instant::now();
+        let Some((node, start)) = self.stack.pop() else {
+            return;
+        };
+        if self.current.len() <= node {
+            self.current.resize(node + 1, SpanStats::default());
+        }
+        let stats = &mut self.current[node];
+        stats.calls += 1;
+        stats.total += end - start;
+        if let Some(trace) = &mut self.trace {
+            if trace.len() < TRACE_LIMIT {
+                trace.push(TraceEvent {
+                    node,
+                    start: start - self.origin,
+                    duration: end - start,
+                });
+            } else {
+                self.dropped += 1;
+            }
+        }
+    }
+
+    /// Closes spans the guest left open and starts summarizing a new frame.
+    pub fn end_frame(&mut self) {
+        while !self.stack.is_empty() {
+            self.end();
+        }
+        let current = std::mem::take(&mut self.current);
+        self.last_frame.spans = current
+            .into_iter()
+            .enumerate()
+            .filter(|(_, stats)| stats.calls > 0)
+            .map(|(node, stats)| (self.path(node), stats))
+            .collect();
+    }
+
+    /// The names of `node` and the spans around it, outermost first.
+    fn path(&self, mut node: usize) -> Vec<String> {
+        let mut path = vec![self.nodes[node].name.clone()];
+        while let Some(parent) = self.nodes[node].parent {
+            path.push(self.nodes[parent].name.clone());
+            node = parent;
+        }
+        path.reverse();
+        path
+    }
+
+    /// How many spans were left out of the trace because it was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    pub fn last_frame(&self) -> &FrameProfile {
+        &self.last_frame
+    }
+
+    /// Writes the recorded spans in the Chrome trace event format, which
+    /// chrome://tracing and Perfetto can open.
+    pub fn write_chrome_trace(&self, mut out: impl Write) -> std::io::Result<()> {
+        write!(out, "{{\"traceEvents\":[")?;
+        for (i, event) in self.trace.iter().flatten().enumerate() {
+            if i > 0 {
+                write!(out, ",")?;
+            }
+            write!(
+                out,
+                "\n{{\"name\":\"{}\",\"ph\":\"X\",\"pid\":1,\"tid\":1,\"ts\":{:.3},\"dur\":{:.3}}}",
+                escape_json(&self.nodes[event.node].name),
+                event.start.as_secs_f64() * 1e6,
+                event.duration.as_secs_f64() * 1e6,
+            )?;
+        }
+        writeln!(out, "\n]}}")
+    }
+}
+
+impl fmt::Display for FrameProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (path, stats) in &self.spans {
+            writeln!(
+                f,
+                "{:indent$}{} {:.3}ms ({} calls)",
+                "",
+                path.last().map(String::as_str).unwrap_or_default(),
+                stats.total.as_secs_f64() * 1e3,
+                stats.calls,
+                indent = (path.len() - 1) * 2,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn escape_json(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_frame_profile() {
+        let mut recorder = SpanRecorder::new(true);
+        recorder.begin("render");
+        for _ in 0..3 {
+            recorder.begin("gradient");
+            recorder.end();
+        }
+        recorder.begin("unclosed");
+        recorder.end_frame();
+
+        let spans = &recorder.last_frame().spans;
+        let calls = |path: &[&str]| {
+            let path: Vec<String> = path.iter().map(|s| s.to_string()).collect();
+            spans[&path].calls
+        };
+        assert_eq!(calls(&["render"]), 1);
+        assert_eq!(calls(&["render", "gradient"]), 3);
+        assert_eq!(calls(&["render", "unclosed"]), 1);
+
+        let mut json = Vec::new();
+        recorder.write_chrome_trace(&mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert_eq!(json.matches("\"ph\":\"X\"").count(), 5);
+
+        // The same name in another span is another path, and paths are only
+        // stored once however often they are seen.
+        recorder.begin("gradient");
+        recorder.end();
+        for _ in 0..TRACE_LIMIT {
+            recorder.begin("render");
+            recorder.begin("gradient");
+            recorder.end();
+            recorder.end();
+        }
+        recorder.end_frame();
+        assert_eq!(recorder.nodes.len(), 4);
+        let spans = &recorder.last_frame().spans;
+        assert_eq!(spans.len(), 3);
+        assert_eq!(spans[&vec!["gradient".to_string()]].calls, 1);
+        assert_eq!(recorder.trace.as_ref().unwrap().len(), TRACE_LIMIT);
+        assert_eq!(recorder.dropped(), TRACE_LIMIT as u64 + 6);
+    }
+}
@@ -0,0 +1,457 @@
+use crate::http::{self, Head};
+use crate::playback::Playback;
+use crate::plugin::Param;
+use crate::supervisor::Supervisor;
+use base64::Engine;
+use serde::{Serialize, Serializer};
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::io::{BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+/// Something a remote asked the presenter to do.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum Command {
+    State,
+    Play,
+    Pause,
+    Seek(f64),
+    Reload,
+    Params,
+    SetParam(String, f64),
+}
+
+/// What a command returns; it is sent to the remote as JSON.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(untagged)]
+#[non_exhaustive]
+pub enum Reply {
+    State {
+        playing: bool,
+        time: f64,
+        failure: Option<String>,
+    },
+    /// Sent as an object from names to values.
+    #[serde(serialize_with = "params_object")]
+    Params(Vec<Param>),
+    /// Sent as an empty object.
+    #[serde(serialize_with = "empty_object")]
+    Done,
+}
+
+fn params_object<S: Serializer>(params: &[Param], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(params.iter().map(|param| (&param.name, param.value)))
+}
+
+fn empty_object<S: Serializer>(serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(std::iter::empty::<((), ())>())
+}
+
+#[derive(Serialize)]
+struct Error<'a> {
+    error: &'a str,
+}
+
+/// The most a request body may be. Bodies are single numbers.
+const MAX_BODY: u64 = 1024;
+
+struct Request {
+    command: Command,
+    reply: Sender<Result<Reply, String>>,
+}
+
+type Subscribers = Arc<Mutex<Vec<Sender<String>>>>;
+
+/// Lets another machine control a running demo over HTTP:
+///
+/// - `GET /state` returns whether the demo is playing, its time in seconds
+///   and why the guest failed, if it did.
+/// - `POST /play`, `POST /pause` and `POST /seek` with the time as a JSON
+///   number in the body control playback and return the new state.
+/// - `POST /reload` loads the module again from its file.
+/// - `GET /params` returns the guest's parameters and `PUT /params/<name>`
+///   with the value as the body sets one. The name is percent-encoded.
+/// - `GET /logs` upgrades to a WebSocket that gets every log message as a
+///   text message.
+///
+/// Requests are served on background threads, but commands only run when
+/// the presenter calls `poll`, in between frames.
+pub struct Remote {
+    addr: SocketAddr,
+    requests: Receiver<Request>,
+    subscribers: Subscribers,
+}
+
+impl Remote {
+    pub fn start(addr: impl ToSocketAddrs) -> anyhow::Result<Remote> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let (sender, requests) = mpsc::channel();
+        let subscribers = Subscribers::default();
+        let logs = subscribers.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let requests = sender.clone();
+                let logs = logs.clone();
+                std::thread::spawn(move || {
+                    if let Err(error) = serve(stream, requests, logs) {
+                        eprintln!("remote control: {error:#}");
+                    }
+                });
+            }
+        });
+        Ok(Remote {
+            addr,
+            requests,
+            subscribers,
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// A handler for `DemoRunner::set_log_handler` that forwards messages to
+    /// every `/logs` WebSocket.
+    pub fn log_handler(&self) -> impl Fn(&str) + Send + Sync + 'static {
+        let subscribers = self.subscribers.clone();
+        move |message| {
+            subscribers
+                .lock()
+                .unwrap()
+                .retain(|subscriber| subscriber.send(message.to_string()).is_ok());
+        }
+    }
+
+    /// Runs the commands that arrived since the last call.
+    pub fn poll(&self, mut handle: impl FnMut(Command) -> anyhow::Result<Reply>) {
+        while let Ok(request) = self.requests.try_recv() {
+            let reply = handle(request.command).map_err(|error| format!("{error:#}"));
+            // The client may have hung up in the meantime.
+            let _ = request.reply.send(reply);
+        }
+    }
+}
+
+/// Runs `command` against the demo a presenter shows.
+pub fn apply(
+    command: Command,
+    playback: &mut Playback,
+    demo: &mut Supervisor,
+) -> anyhow::Result<Reply> {
+    match command {
+        Command::State => {}
+        Command::Play => playback.play(),
+        Command::Pause => playback.pause(),
+        Command::Seek(time) => playback.seek(time),
+        Command::Reload => {
+            demo.reload()?;
+            return Ok(Reply::Done);
+        }
+        Command::Params => return Ok(Reply::Params(demo.runner().params()?)),
+        Command::SetParam(name, value) => {
+            demo.runner().set_param(&name, value)?;
+            return Ok(Reply::Done);
+        }
+    }
+    Ok(Reply::State {
+        playing: playback.is_playing(),
+        time: playback.time(),
+        failure: demo.failure().map(str::to_string),
+    })
+}
+
+/// Serves one request; connections are not kept alive.
+fn serve(stream: TcpStream, requests: Sender<Request>, logs: Subscribers) -> anyhow::Result<()> {
+    http::set_timeouts(&stream)?;
+    let mut reader = BufReader::new(&stream);
+    let Head {
+        method,
+        path,
+        headers,
+    } = match http::read_head(&mut reader) {
+        Ok(head) => head,
+        Err(error) => return write_response(&stream, 400, &error_json(&format!("{error:#}"))),
+    };
+
+    if method == "GET" && path == "/logs" {
+        return stream_logs(&stream, &headers, logs);
+    }
+
+    // Check the length before reading, so that clients can't make the host
+    // run out of memory.
+    let length = match headers
+        .get("content-length")
+        .map(|length| length.parse::<u64>())
+    {
+        None => 0,
+        Some(Ok(length)) if length <= MAX_BODY => length,
+        Some(Ok(_)) => {
+            let error = format!("the body may be at most {MAX_BODY} bytes");
+            return write_response(&stream, 413, &error_json(&error));
+        }
+        Some(Err(_)) => return write_response(&stream, 400, &error_json("bad Content-Length")),
+    };
+    let mut body = Vec::new();
+    reader.take(length).read_to_end(&mut body)?;
+    let body = String::from_utf8_lossy(&body);
+
+    let (status, json) = match route(&method, &path, &body) {
+        Ok(command) => {
+            let (reply, receiver) = mpsc::channel();
+            requests.send(Request { command, reply })?;
+            match receiver.recv() {
+                Ok(Ok(reply)) => (200, serde_json::to_string(&reply)?),
+                Ok(Err(error)) => (500, error_json(&error)),
+                Err(_) => (503, error_json("the presenter has stopped")),
+            }
+        }
+        Err((status, error)) => (status, error_json(&error)),
+    };
+    write_response(&stream, status, &json)
+}
+
+fn route(method: &str, path: &str, body: &str) -> Result<Command, (u16, String)> {
+    let number = || {
+        serde_json::from_str::<f64>(body)
+            .map_err(|_| (400, format!("expected a number, got {body:?}")))
+    };
+    match (method, path) {
+        ("GET", "/state") => Ok(Command::State),
+        ("POST", "/play") => Ok(Command::Play),
+        ("POST", "/pause") => Ok(Command::Pause),
+        ("POST", "/seek") => Ok(Command::Seek(number()?)),
+        ("POST", "/reload") => Ok(Command::Reload),
+        ("GET", "/params") => Ok(Command::Params),
+        ("PUT", path) if path.starts_with("/params/") => {
+            let name = &path["/params/".len()..];
+            let name = percent_decode(name)
+                .ok_or_else(|| (400, format!("bad parameter name {name:?}")))?;
+            Ok(Command::SetParam(name, number()?))
+        }
+        _ => Err((404, format!("no route for {method} {path}"))),
+    }
+}
+
+fn write_response(mut stream: &TcpStream, status: u16, json: &str) -> anyhow::Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        413 => "Payload Too Large",
+        500 => "Internal Server Error",
+        _ => "Service Unavailable",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status} {reason}\r\n\
+         Content-Type: application/json\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{json}",
+        json.len()
+    )?;
+    Ok(())
+}
+
+fn error_json(error: &str) -> String {
+    serde_json::to_string(&Error { error }).unwrap()
+}
+
+/// Decodes a percent-encoded path segment, if it is valid UTF-8.
+fn percent_decode(segment: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(segment.len());
+    let mut rest = segment.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            // `from_str_radix` would take a sign, as in `%+1`.
+            let hex = tail
+                .get(..2)
+                .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))?;
+            bytes.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Completes the WebSocket handshake and sends log messages until the client
+/// goes away. Only the server half of the protocol that sends unfragmented
+/// text messages is needed; anything the client sends is ignored.
+fn stream_logs(
+    mut stream: &TcpStream,
+    headers: &HashMap<String, String>,
+    logs: Subscribers,
+) -> anyhow::Result<()> {
+    const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+    let Some(key) = headers.get("sec-websocket-key") else {
+        return write_response(stream, 400, &error_json("expected a WebSocket handshake"));
+    };
+    let accept = base64::engine::general_purpose::STANDARD
+        .encode(Sha1::digest(format!("{key}{GUID}").as_bytes()));
+
+    // Subscribe before answering, so nothing logged after the client sees
+    // the handshake is lost.
+    let (sender, receiver) = mpsc::channel();
+    logs.lock().unwrap().push(sender);
+    write!(
+        stream,
+        "HTTP/1.1 101 Switching Protocols\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {accept}\r\n\r\n"
+    )?;
+    for message in receiver {
+        if write_text_frame(stream, &message).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn write_text_frame(mut out: impl Write, text: &str) -> std::io::Result<()> {
+    let mut frame = vec![0x81];
+    let len = text.len();
+    if len < 126 {
+        frame.push(len as u8);
+    } else if len <= u16::MAX as usize {
+        frame.push(126);
+        frame.extend_from_slice(&(len as u16).to_be_bytes());
+    } else {
+        frame.push(127);
+        frame.extend_from_slice(&(len as u64).to_be_bytes());
+    }
+    frame.extend_from_slice(text.as_bytes());
+    out.write_all(&frame)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::BufRead;
+    use std::time::Duration;
+
+    fn request(addr: SocketAddr, method: &str, path: &str, body: &str) -> String {
+        raw_request(addr, method, path, &body.len().to_string(), body)
+    }
+
+    fn raw_request(addr: SocketAddr, method: &str, path: &str, length: &str, body: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "{method} {path} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {length}\r\n\r\n{body}",
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_remote_http() {
+        let remote = Remote::start("127.0.0.1:0").unwrap();
+        let addr = remote.addr();
+        let client = std::thread::spawn(move || {
+            [
+                request(addr, "POST", "/pause", ""),
+                request(addr, "POST", "/seek", "2.5"),
+                request(addr, "PUT", "/params/hue", "0.25"),
+                request(addr, "GET", "/params", ""),
+                request(addr, "PUT", "/params/size", "1"),
+                request(addr, "POST", "/seek", "soon"),
+                request(addr, "GET", "/nothing", ""),
+                request(addr, "PUT", "/params/hue%20shift", "3"),
+                request(addr, "PUT", "/params/hue%2", "3"),
+                // Neither is read, let alone allocated for.
+                raw_request(addr, "POST", "/seek", &u64::MAX.to_string(), "1"),
+                raw_request(addr, "POST", "/seek", "-1", "1"),
+            ]
+        });
+
+        let mut playback = Playback::default();
+        let mut hue = 0.5;
+        while !client.is_finished() {
+            remote.poll(|command| {
+                match command {
+                    Command::Pause => playback.pause(),
+                    Command::Seek(time) => playback.seek(time),
+                    Command::Params => {
+                        return Ok(Reply::Params(vec![Param {
+                            name: "hue".to_string(),
+                            value: hue,
+                        }]))
+                    }
+                    Command::SetParam(name, value) if name == "hue" => hue = value,
+                    Command::SetParam(name, value) if name == "hue shift" => hue += value,
+                    command => anyhow::bail!("unexpected {command:?}"),
+                }
+                Ok(Reply::State {
+                    playing: playback.is_playing(),
+                    time: playback.time(),
+                    failure: None,
+                })
+            });
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        let responses = client.join().unwrap();
+        assert_eq!(percent_decode("a%2Fb%c3%a9"), Some("a/bé".to_string()));
+        assert_eq!(percent_decode("%ff"), None);
+        assert_eq!(percent_decode("%+1"), None);
+        assert_eq!(serde_json::to_string(&Reply::Done).unwrap(), "{}");
+        let body = |i: usize| responses[i].split("\r\n\r\n").nth(1).unwrap();
+        assert!(responses[0].starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(body(1), r#"{"playing":false,"time":2.5,"failure":null}"#);
+        assert_eq!(body(3), r#"{"hue":0.25}"#);
+        assert!(responses[4].starts_with("HTTP/1.1 500 "));
+        assert_eq!(body(4), r#"{"error":"unexpected SetParam(\"size\", 1.0)"}"#);
+        assert!(responses[5].starts_with("HTTP/1.1 400 "));
+        assert!(responses[6].starts_with("HTTP/1.1 404 "));
+        assert!(responses[7].starts_with("HTTP/1.1 200 "));
+        assert_eq!(hue, 3.25);
+        assert!(responses[8].starts_with("HTTP/1.1 400 "));
+        assert_eq!(body(8), r#"{"error":"bad parameter name \"hue%2\""}"#);
+        assert!(responses[9].starts_with("HTTP/1.1 413 "));
+        assert_eq!(body(9), r#"{"error":"the body may be at most 1024 bytes"}"#);
+        assert!(responses[10].starts_with("HTTP/1.1 400 "));
+    }
+
+    #[test]
+    fn test_remote_logs() {
+        let remote = Remote::start("127.0.0.1:0").unwrap();
+        let mut stream = TcpStream::connect(remote.addr()).unwrap();
+        // The handshake from RFC 6455, section 1.3.
+        write!(
+            stream,
+            "GET /logs HTTP/1.1\r\n\
+             Host: localhost\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+             Sec-WebSocket-Version: 13\r\n\r\n"
+        )
+        .unwrap();
+        let mut reader = BufReader::new(&stream);
+        let mut handshake = String::new();
+        while !handshake.ends_with("\r\n\r\n") {
+            reader.read_line(&mut handshake).unwrap();
+        }
+        assert!(handshake.starts_with("HTTP/1.1 101 "));
+        assert!(handshake.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+
+        let log = remote.log_handler();
+        log("hello");
+        log(&"x".repeat(200));
+        let mut frame = [0; 7];
+        reader.read_exact(&mut frame).unwrap();
+        assert_eq!(&frame, b"\x81\x05hello");
+        let mut header = [0; 4];
+        reader.read_exact(&mut header).unwrap();
+        assert_eq!(header, [0x81, 126, 0, 200]);
+    }
+}
@@ -0,0 +1,304 @@
+use crate::catalogue;
+use crate::plugin::{DemoRunner, FrameView, Input, Rect};
+use crate::postprocess::Image;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// The version of the replay file format, bumped when old replays stop
+/// meaning the same thing.
+pub const VERSION: u32 = 1;
+
+/// A call into the guest, as `DemoRunner::start_recording` records it.
+/// Sizes are `[width, height]`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum Event {
+    Dpi {
+        dpi: i32,
+    },
+    Dimensions {
+        dpi: i32,
+        min: [i32; 2],
+        preferred: [i32; 2],
+        max: [i32; 2],
+    },
+    Param {
+        name: String,
+        value: f64,
+    },
+    PointerMove {
+        x: i32,
+        y: i32,
+    },
+    PointerButton {
+        button: i32,
+        pressed: bool,
+    },
+    Key {
+        code: i32,
+        pressed: bool,
+    },
+    /// A frame rendered at `time`, and the SHA-1 of its pixels if the guest
+    /// didn't fail rendering it.
+    Frame {
+        time: f64,
+        size: [i32; 2],
+        sha1: Option<String>,
+    },
+    /// The supervisor started the guest over after it failed, at the DPI
+    /// it was last given.
+    Restart {
+        #[serde(default)]
+        dpi: Option<i32>,
+    },
+}
+
+impl From<Input> for Event {
+    fn from(input: Input) -> Event {
+        match input {
+            Input::PointerMove { x, y } => Event::PointerMove { x, y },
+            Input::PointerButton { button, pressed } => Event::PointerButton { button, pressed },
+            Input::Key { code, pressed } => Event::Key { code, pressed },
+        }
+    }
+}
+
+/// Everything a demo was asked to do while it was recorded, to do again
+/// with `play`. Guests only see the host through these calls, so the same
+/// module renders the same frames from them on any machine.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct Replay {
+    pub version: u32,
+    /// The SHA-1 of the module that was recorded.
+    pub module: String,
+    pub events: Vec<Event>,
+}
+
+impl Replay {
+    pub fn new(module: String, events: Vec<Event>) -> Replay {
+        Replay {
+            version: VERSION,
+            module,
+            events,
+        }
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Replay> {
+        let data = std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        let replay: Replay =
+            serde_json::from_slice(&data).with_context(|| format!("parsing {}", path.display()))?;
+        anyhow::ensure!(
+            replay.version == VERSION,
+            "{} is a version {} replay; this host plays version {VERSION}",
+            path.display(),
+            replay.version
+        );
+        Ok(replay)
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let file =
+            std::fs::File::create(path).with_context(|| format!("creating {}", path.display()))?;
+        serde_json::to_writer(std::io::BufWriter::new(file), self)
+            .with_context(|| format!("writing {}", path.display()))
+    }
+
+    pub fn frames(&self) -> usize {
+        self.events
+            .iter()
+            .filter(|event| matches!(event, Event::Frame { .. }))
+            .count()
+    }
+}
+
+/// How a replay went.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[non_exhaustive]
+pub struct Report {
+    pub frames: usize,
+    /// Frames that came out different from the recording, including ones
+    /// that failed now but rendered then.
+    pub mismatches: Vec<usize>,
+    /// Where the guest failed, by the frame it was at, like it may have
+    /// while it was recorded.
+    pub failures: Vec<(usize, String)>,
+}
+
+/// The SHA-1 of the pixels of `frame`, as recorded in `Event::Frame`.
+pub fn frame_hash(frame: &FrameView<'_>) -> String {
+    catalogue::sha1_hex(&Image::from_rows(frame.data, &frame.size, frame.stride).data)
+}
+
+/// Makes the calls in `replay` on a freshly created `runner` and hands each
+/// frame to `handle` with its index. Failures in the guest are reported and
+/// the replay goes on, as the supervisor did while recording; errors from
+/// `handle` stop it.
+pub fn play(
+    replay: &Replay,
+    runner: &mut DemoRunner,
+    mut handle: impl FnMut(usize, &FrameView<'_>) -> anyhow::Result<()>,
+) -> anyhow::Result<Report> {
+    let mut report = Report::default();
+    let rect = |[width, height]: [i32; 2]| Rect { width, height };
+    for event in &replay.events {
+        let result = match event {
+            Event::Dpi { dpi } => runner.call_set_dpi(*dpi).map(|_| ()),
+            Event::Dimensions {
+                dpi,
+                min,
+                preferred,
+                max,
+            } => runner
+                .call_set_dimensions(*dpi, &rect(*min), &rect(*preferred), &rect(*max))
+                .map(|_| ()),
+            Event::Param { name, value } => runner.set_param(name, *value),
+            Event::PointerMove { x, y } => runner.send_input(&Input::PointerMove { x: *x, y: *y }),
+            Event::PointerButton { button, pressed } => runner.send_input(&Input::PointerButton {
+                button: *button,
+                pressed: *pressed,
+            }),
+            Event::Key { code, pressed } => runner.send_input(&Input::Key {
+                code: *code,
+                pressed: *pressed,
+            }),
+            Event::Restart { dpi } => {
+                if let Some(dpi) = dpi {
+                    runner.set_dpi_on_restart(*dpi);
+                }
+                runner.restart()
+            }
+            Event::Frame { time, size, sha1 } => {
+                let index = report.frames;
+                report.frames += 1;
+                match runner.render_frame(*time, &rect(*size)) {
+                    Ok(frame) => {
+                        if sha1.as_ref() != Some(&frame_hash(&frame)) {
+                            report.mismatches.push(index);
+                        }
+                        handle(index, &frame)?;
+                        Ok(())
+                    }
+                    Err(error) => {
+                        if sha1.is_some() {
+                            report.mismatches.push(index);
+                        }
+                        Err(error)
+                    }
+                }
+            }
+        };
+        if let Err(error) = result {
+            report
+                .failures
+                .push((report.frames.saturating_sub(1), format!("{error:#}")));
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_replay() {
+        let wat = r#"(module
+            (memory (export "memory") 1)
+            (global $x (mut i32) (i32.const 0))
+            (global $speed (mut f64) (f64.const 1))
+            (func (export "pointer_move") (param i32 i32) (global.set $x (local.get 0)))
+            (func (export "param_name") (param i32) (result i32)
+                (select (i32.const 0x200) (i32.const 0) (i32.eqz (local.get 0))))
+            (func (export "get_param") (param i32) (result f64) global.get $speed)
+            (func (export "set_param") (param i32 f64) (global.set $speed (local.get 1)))
+            (data (i32.const 0x200) "speed\00")
+            (func (export "render") (param f64 i32 i32) (result i32)
+                (if (f64.gt (local.get 0) (f64.const 10)) (then unreachable))
+                (i32.store (i32.const 0x100)
+                    (i32.add (global.get $x)
+                        (i32.trunc_f64_s (f64.mul (local.get 0) (global.get $speed)))))
+                i32.const 0x100))"#;
+        let size = Rect {
+            width: 1,
+            height: 1,
+        };
+        let mut runner = crate::create_wat(wat).unwrap();
+        runner.set_param("speed", 2.0).unwrap();
+        runner.start_recording();
+        runner.render_frame(1.5, &size).unwrap();
+        runner
+            .send_input(&Input::PointerMove { x: 7, y: 0 })
+            .unwrap();
+        runner.render_frame(3.0, &size).unwrap();
+        assert!(runner.render_frame(11.0, &size).is_err());
+        // Failed sets aren't recorded.
+        assert!(runner.set_param("hue", 1.0).is_err());
+        // As the supervisor does when the DPI changes while the guest is down.
+        runner.set_dpi_on_restart(144);
+        runner.restart().unwrap();
+        runner.render_frame(4.0, &size).unwrap();
+        let replay = runner.take_recording().unwrap();
+        assert!(runner.take_recording().is_none());
+        assert_eq!(replay.frames(), 4);
+        assert_eq!(
+            replay.events[..2],
+            [
+                Event::Param {
+                    name: "speed".to_string(),
+                    value: 2.0
+                },
+                Event::Frame {
+                    time: 1.5,
+                    size: [1, 1],
+                    sha1: Some(catalogue::sha1_hex(&[3, 0, 0, 0]))
+                },
+            ]
+        );
+        assert_eq!(replay.events[5], Event::Restart { dpi: Some(144) });
+        assert_eq!(replay.events.len(), 7);
+        assert_eq!(
+            serde_json::from_str::<Event>(r#"{"event":"restart"}"#).unwrap(),
+            Event::Restart { dpi: None }
+        );
+
+        let path = std::env::temp_dir().join(format!("demo-replay-{}.json", std::process::id()));
+        replay.save(&path).unwrap();
+        let loaded = Replay::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, replay);
+
+        let mut frames = Vec::new();
+        let mut runner = crate::create_wat(wat).unwrap();
+        let report = play(&loaded, &mut runner, |index, frame| {
+            frames.push((index, frame.data[0]));
+            Ok(())
+        })
+        .unwrap();
+        // The restarted guest keeps its parameter but not the pointer.
+        assert_eq!(frames, [(0, 3), (1, 13), (3, 8)]);
+        assert_eq!(report.frames, 4);
+        assert!(report.mismatches.is_empty());
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(report.failures[0].0, 2);
+        assert!(report.failures[0].1.contains("unreachable"));
+
+        // A different module may not render the same frames.
+        let fixed = wat.replace(
+            "(global.set $x (local.get 0))",
+            "(global.set $x (i32.const 1))",
+        );
+        let mut other = crate::create_wat(&fixed).unwrap();
+        let report = play(&loaded, &mut other, |_, _| Ok(())).unwrap();
+        assert_eq!(report.mismatches, [1]);
+
+        // Nor fail only where the recording did.
+        let trapping = wat.replace("(f64.const 10)", "(f64.const 3.5)");
+        let mut other = crate::create_wat(&trapping).unwrap();
+        let report = play(&loaded, &mut other, |_, _| Ok(())).unwrap();
+        assert_eq!(report.mismatches, [3]);
+        assert_eq!(report.failures.len(), 2);
+    }
+}
@@ -0,0 +1,231 @@
+use crate::plugin::{FrameView, Rect};
+use crate::postprocess::{self, Filter, Image, Pass};
+use serde::Deserialize;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// How big frames are rendered compared to the output they are shown in.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "ScaleValue")]
+#[non_exhaustive]
+pub enum RenderScale {
+    /// A fraction of the output size, up to 1.
+    Fixed(f64),
+    /// Whatever holds the target frame time, from `MIN_SCALE` up to 1.
+    Auto,
+}
+
+impl Default for RenderScale {
+    fn default() -> Self {
+        RenderScale::Fixed(1.0)
+    }
+}
+
+impl FromStr for RenderScale {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        if text == "auto" {
+            return Ok(RenderScale::Auto);
+        }
+        let scale = text
+            .parse()
+            .map_err(|_| format!("expected a number or `auto`, got {text:?}"))?;
+        RenderScale::fixed(scale)
+    }
+}
+
+impl RenderScale {
+    fn fixed(scale: f64) -> Result<RenderScale, String> {
+        if scale > 0.0 && scale <= 1.0 {
+            Ok(RenderScale::Fixed(scale))
+        } else {
+            Err(format!("render scale {scale} is not above 0 and at most 1"))
+        }
+    }
+}
+
+/// Config files give the scale as a number or `"auto"`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ScaleValue {
+    Number(f64),
+    Text(String),
+}
+
+impl TryFrom<ScaleValue> for RenderScale {
+    type Error = String;
+
+    fn try_from(value: ScaleValue) -> Result<Self, Self::Error> {
+        match value {
+            ScaleValue::Number(scale) => RenderScale::fixed(scale),
+            ScaleValue::Text(text) => text.parse(),
+        }
+    }
+}
+
+/// The smallest scale the automatic mode goes down to.
+pub const MIN_SCALE: f64 = 0.25;
+
+/// The automatic mode moves in steps this big, so the guest doesn't have to
+/// resize its framebuffer for every small change.
+const STEP: f64 = 1.0 / 16.0;
+
+/// How many frames the automatic mode averages before adjusting.
+const ADJUST_EVERY: u32 = 15;
+
+/// Picks the size frames are rendered at and scales them up to the output.
+#[derive(Clone, Debug)]
+pub struct Scaler {
+    mode: RenderScale,
+    filter: Filter,
+    target: Duration,
+    scale: f64,
+    frames: u32,
+    total: Duration,
+    last: Duration,
+}
+
+impl Scaler {
+    /// `target` is the render time the automatic mode aims for.
+    pub fn new(mode: RenderScale, filter: Filter, target: Duration) -> Scaler {
+        Scaler {
+            mode,
+            filter,
+            target,
+            scale: match mode {
+                RenderScale::Fixed(scale) => scale,
+                RenderScale::Auto => 1.0,
+            },
+            frames: 0,
+            total: Duration::ZERO,
+            last: Duration::ZERO,
+        }
+    }
+
+    pub fn scale(&self) -> f64 {
+        self.scale
+    }
+
+    /// How long the guest took to render the last frame in `render`.
+    pub fn render_time(&self) -> Duration {
+        self.last
+    }
+
+    /// The size to render at for an `output` this big.
+    pub fn render_size(&self, output: &Rect) -> Rect {
+        let scaled = |length: i32| ((length as f64 * self.scale).round() as i32).max(1);
+        Rect {
+            width: scaled(output.width),
+            height: scaled(output.height),
+        }
+    }
+
+    /// Maps a position in an `output` this big to the pixel of the rendered
+    /// frame under it, so input lands where the guest drew.
+    pub fn render_position(&self, output: &Rect, (x, y): (i32, i32)) -> (i32, i32) {
+        let size = self.render_size(output);
+        let scaled = |position: i32, length: i32, output: i32| {
+            ((position as f64 + 0.5) * length as f64 / output.max(1) as f64).floor() as i32
+        };
+        (
+            scaled(x, size.width, output.width),
+            scaled(y, size.height, output.height),
+        )
+    }
+
+    /// Records how long rendering a frame took. The automatic mode changes
+    /// the scale from time to time, and returns whether it did.
+    pub fn record(&mut self, elapsed: Duration) -> bool {
+        if self.mode != RenderScale::Auto {
+            return false;
+        }
+        self.frames += 1;
+        self.total += elapsed;
+        if self.frames < ADJUST_EVERY {
+            return false;
+        }
+        let average = self.total.as_secs_f64() / self.frames as f64;
+        self.frames = 0;
+        self.total = Duration::ZERO;
+        // Render time grows with the number of pixels, so with the square of
+        // the scale. Going up only a step at a time avoids overshooting.
+        let ideal = self.scale * (self.target.as_secs_f64() / average).sqrt();
+        let scale = ((ideal / STEP).floor() * STEP)
+            .min(self.scale + STEP)
+            .clamp(MIN_SCALE, 1.0);
+        let changed = scale != self.scale;
+        self.scale = scale;
+        changed
+    }
+
+    /// `frame` at the `output` size.
+    pub fn upscale(&self, frame: &FrameView<'_>, output: &Rect) -> Image {
+        let image = Image::from_rows(frame.data, &frame.size, frame.stride);
+        postprocess::resize(&image, output, self.filter)
+    }
+
+    /// Renders a frame for an `output` this big with `render_frame`, e.g. a
+    /// runner's or supervisor's, and returns it scaled up and run through
+    /// `passes`.
+    pub fn render<'a>(
+        &mut self,
+        render_frame: impl FnOnce(&Rect) -> anyhow::Result<FrameView<'a>>,
+        output: &Rect,
+        passes: &[Pass],
+    ) -> anyhow::Result<Image> {
+        let start = Instant::now();
+        let frame = render_frame(&self.render_size(output))?;
+        let elapsed = start.elapsed();
+        let image = self.upscale(&frame, output);
+        self.record(elapsed);
+        self.last = elapsed;
+        postprocess::apply(passes, image)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_scaler() {
+        let output = Rect {
+            width: 640,
+            height: 480,
+        };
+        let half = Scaler::new("0.5".parse().unwrap(), Filter::Nearest, Duration::ZERO);
+        assert_eq!(
+            half.render_size(&output),
+            Rect {
+                width: 320,
+                height: 240
+            }
+        );
+        // Input in the output lands on the half-size frame's pixels.
+        assert_eq!(half.render_position(&output, (0, 0)), (0, 0));
+        assert_eq!(half.render_position(&output, (639, 479)), (319, 239));
+        assert_eq!(half.render_position(&output, (321, 100)), (160, 50));
+        let full = Scaler::new("1".parse().unwrap(), Filter::Nearest, Duration::ZERO);
+        assert_eq!(full.render_position(&output, (321, 100)), (321, 100));
+        assert!("1.5".parse::<RenderScale>().is_err());
+        assert!("fast".parse::<RenderScale>().is_err());
+
+        let mut auto = Scaler::new(
+            RenderScale::Auto,
+            Filter::Bilinear,
+            Duration::from_millis(10),
+        );
+        let mut record = |millis, frames| {
+            let mut changed = false;
+            for _ in 0..frames {
+                changed |= auto.record(Duration::from_millis(millis));
+            }
+            (changed, auto.scale())
+        };
+        assert_eq!(record(40, ADJUST_EVERY - 1), (false, 1.0));
+        assert_eq!(record(40, 1), (true, 0.5));
+        assert_eq!(record(2, ADJUST_EVERY), (true, 0.5625));
+        assert_eq!(record(1000, ADJUST_EVERY), (true, MIN_SCALE));
+    }
+}
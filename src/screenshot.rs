@@ -0,0 +1,153 @@
+use crate::catalogue;
+use crate::hud::Hud;
+use crate::image;
+use crate::plugin::{DemoRunner, Rect};
+use crate::postprocess::{self, Image, Pass};
+use anyhow::Context;
+use std::path::Path;
+
+/// `size` made small enough to fit in `max`, keeping its aspect ratio.
+pub fn fit(size: &Rect, max: &Rect) -> Rect {
+    let scale = (max.width as f64 / size.width as f64)
+        .min(max.height as f64 / size.height as f64)
+        .min(1.0);
+    let scaled = |length: i32| ((length as f64 * scale).floor() as i32).max(1);
+    Rect {
+        width: scaled(size.width),
+        height: scaled(size.height),
+    }
+}
+
+/// Renders the frame at `time` again, `size` frame pixels big or as close
+/// as fits in `max`, and runs it through `passes`. Guests only promise to
+/// handle frames up to the maximum size they were given, which is what
+/// `max` should be.
+///
+/// The next frame rendered at the usual size makes the guest resize its
+/// framebuffer back and report the whole frame as damaged.
+pub fn capture(
+    runner: &mut DemoRunner,
+    time: f64,
+    size: &Rect,
+    max: &Rect,
+    passes: &[Pass],
+) -> anyhow::Result<Image> {
+    let size = fit(size, max);
+    let frame = runner
+        .render_frame(time, &size)
+        .with_context(|| format!("rendering a {}x{} frame", size.width, size.height))?;
+    let image = Image::from_rows(frame.data, &frame.size, frame.stride);
+    postprocess::apply(passes, image)
+}
+
+/// PNG text chunks recording which demo a screenshot of the frame at `time`
+/// comes from: the demo's title and author under the standard keywords, and
+/// the time, module name and other metadata under `demo:` ones.
+pub fn text(runner: &DemoRunner, time: f64) -> Vec<(String, String)> {
+    let mut text = vec![
+        (
+            "Software".to_string(),
+            format!("demo {}", env!("CARGO_PKG_VERSION")),
+        ),
+        ("demo:time".to_string(), time.to_string()),
+    ];
+    if let Some(path) = runner.path() {
+        text.push(("demo:module".to_string(), catalogue::module_name(path)));
+    }
+    for (key, value) in runner.metadata() {
+        let keyword = match key.as_str() {
+            "title" => "Title".to_string(),
+            "author" => "Author".to_string(),
+            _ => format!("demo:{key}"),
+        };
+        // Keywords are limited to 79 printable Latin-1 characters; metadata
+        // that doesn't fit is left out rather than failing the screenshot.
+        if keyword.len() <= 79 && keyword.chars().all(|c| c.is_ascii_graphic() || c == ' ') {
+            text.push((keyword, value.clone()));
+        }
+    }
+    text
+}
+
+/// Captures the frame at `time` like `capture`, draws `hud` on it if it is
+/// visible, and saves it to `path` as a PNG image with `text` chunks.
+pub fn save(
+    runner: &mut DemoRunner,
+    time: f64,
+    size: &Rect,
+    max: &Rect,
+    passes: &[Pass],
+    hud: &Hud,
+    path: &Path,
+) -> anyhow::Result<Image> {
+    let mut image = capture(runner, time, size, max, passes)?;
+    hud.draw_demo(&mut image, time, runner);
+    let file =
+        std::fs::File::create(path).with_context(|| format!("creating {}", path.display()))?;
+    image::write_png_with_text(
+        std::io::BufWriter::new(file),
+        &image.data,
+        &image.size,
+        &text(runner, time),
+    )?;
+    Ok(image)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_screenshot() {
+        let dir = std::env::temp_dir().join(format!("demo-screenshot-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let module = dir.join("shot.wat");
+        // Fills the frame with the time, in a framebuffer at address 0.
+        std::fs::write(
+            &module,
+            r#"(module
+                (memory (export "memory") 1)
+                (@custom "demo" "title = Shot\nauthor = Someone")
+                (func (export "render") (param f64 i32 i32) (result i32)
+                    (memory.fill (i32.const 0)
+                        (i32.trunc_f64_u (local.get 0))
+                        (i32.mul (i32.mul (local.get 1) (local.get 2)) (i32.const 4)))
+                    i32.const 0))"#,
+        )
+        .unwrap();
+        let mut runner = crate::create_file(&module).unwrap();
+
+        let max = Rect {
+            width: 64,
+            height: 64,
+        };
+        let size = Rect {
+            width: 200,
+            height: 100,
+        };
+        let path = dir.join("shot.png");
+        let image = save(&mut runner, 7.0, &size, &max, &[], &Hud::new(false), &path).unwrap();
+        assert_eq!(
+            image.size,
+            Rect {
+                width: 64,
+                height: 32
+            }
+        );
+        assert!(image.data.iter().all(|&byte| byte == 7));
+
+        let decoder = png::Decoder::new(std::fs::File::open(&path).unwrap());
+        let reader = decoder.read_info().unwrap();
+        let text: Vec<_> = reader
+            .info()
+            .uncompressed_latin1_text
+            .iter()
+            .map(|chunk| (chunk.keyword.as_str(), chunk.text.as_str()))
+            .collect();
+        assert!(text.contains(&("demo:time", "7")));
+        assert!(text.contains(&("demo:module", "shot")));
+        assert!(text.contains(&("Title", "Shot")));
+        assert!(text.contains(&("Author", "Someone")));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
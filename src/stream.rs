@@ -0,0 +1,287 @@
+use crate::http;
+use crate::hud::Hud;
+use crate::image;
+use crate::pacing::{Pacer, Wait};
+use crate::playback::Playback;
+use crate::plugin::Rect;
+use crate::postprocess::Pass;
+use crate::scale::Scaler;
+use crate::supervisor::Supervisor;
+use std::io::{BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How frames are sent to a client.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Encoding {
+    Jpeg,
+    Png,
+    /// For each frame, the width and height as little-endian `u32`s followed
+    /// by `width * height` RGBA pixels.
+    Raw,
+}
+
+const BOUNDARY: &str = "frame";
+
+type Subscribers = Arc<Mutex<Vec<(Encoding, SyncSender<Arc<Vec<u8>>>)>>>;
+
+/// Sends every published frame to the clients connected at the time, in the
+/// encoding each asked for. Clients that fall behind skip frames rather than
+/// queueing them up.
+#[derive(Clone)]
+pub struct Broadcast {
+    quality: u8,
+    subscribers: Subscribers,
+}
+
+impl Broadcast {
+    /// `quality` is the JPEG quality, from 1 to 100.
+    pub fn new(quality: u8) -> Broadcast {
+        Broadcast {
+            quality,
+            subscribers: Default::default(),
+        }
+    }
+
+    /// Serves a page with the stream on `/`, and the stream itself as
+    /// `multipart/x-mixed-replace` on `/jpeg` and `/png`.
+    pub fn listen_http(&self, addr: impl ToSocketAddrs) -> anyhow::Result<SocketAddr> {
+        self.listen(addr, |broadcast, stream| broadcast.serve_http(stream))
+    }
+
+    /// Sends raw frames to every client that connects to `addr`.
+    pub fn listen_raw(&self, addr: impl ToSocketAddrs) -> anyhow::Result<SocketAddr> {
+        self.listen(addr, |broadcast, mut stream| {
+            for frame in broadcast.subscribe(Encoding::Raw) {
+                stream.write_all(&frame)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn listen(
+        &self,
+        addr: impl ToSocketAddrs,
+        serve: fn(&Broadcast, TcpStream) -> anyhow::Result<()>,
+    ) -> anyhow::Result<SocketAddr> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let broadcast = self.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let broadcast = broadcast.clone();
+                // Clients hanging up is how streams end, so errors aren't
+                // worth reporting. Clients that stall are dropped.
+                std::thread::spawn(move || {
+                    http::set_timeouts(&stream)?;
+                    serve(&broadcast, stream)
+                });
+            }
+        });
+        Ok(addr)
+    }
+
+    fn subscribe(&self, encoding: Encoding) -> Receiver<Arc<Vec<u8>>> {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        self.subscribers.lock().unwrap().push((encoding, sender));
+        receiver
+    }
+
+    fn serve_http(&self, mut stream: TcpStream) -> anyhow::Result<()> {
+        let path = http::read_head(&mut BufReader::new(&stream))?.path;
+
+        let (encoding, content_type) = match path.as_str() {
+            "/" => {
+                let page = "<!DOCTYPE html><title>demo</title>\
+                            <img src=\"/jpeg\" style=\"max-width:100%\">";
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\n\
+                     Content-Type: text/html\r\n\
+                     Content-Length: {}\r\n\
+                     Connection: close\r\n\r\n{page}",
+                    page.len()
+                )?;
+                return Ok(());
+            }
+            "/jpeg" => (Encoding::Jpeg, "image/jpeg"),
+            "/png" => (Encoding::Png, "image/png"),
+            _ => {
+                write!(
+                    stream,
+                    "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                )?;
+                return Ok(());
+            }
+        };
+
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\n\
+             Content-Type: multipart/x-mixed-replace; boundary={BOUNDARY}\r\n\
+             Cache-Control: no-cache\r\n\
+             Connection: close\r\n\r\n"
+        )?;
+        for image in self.subscribe(encoding) {
+            write!(
+                stream,
+                "--{BOUNDARY}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n\r\n",
+                image.len()
+            )?;
+            stream.write_all(&image)?;
+            write!(stream, "\r\n")?;
+        }
+        Ok(())
+    }
+
+    /// Whether anyone is connected, so frames nobody sees needn't be
+    /// rendered.
+    pub fn has_subscribers(&self) -> bool {
+        !self.subscribers.lock().unwrap().is_empty()
+    }
+
+    /// Sends `data`, `size.width * size.height` RGBA pixels, to every client.
+    /// Each encoding is only produced if some client uses it.
+    pub fn publish(&self, data: &[u8], size: &Rect) -> anyhow::Result<()> {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let mut encoded: Vec<(Encoding, Arc<Vec<u8>>)> = Vec::new();
+        let mut i = 0;
+        while i < subscribers.len() {
+            let encoding = subscribers[i].0;
+            let frame = match encoded.iter().find(|(e, _)| *e == encoding) {
+                Some((_, frame)) => frame.clone(),
+                None => {
+                    let frame = Arc::new(encode(encoding, data, size, self.quality)?);
+                    encoded.push((encoding, frame.clone()));
+                    frame
+                }
+            };
+            match subscribers[i].1.try_send(frame) {
+                Err(TrySendError::Disconnected(_)) => {
+                    subscribers.swap_remove(i);
+                }
+                _ => i += 1,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn encode(encoding: Encoding, data: &[u8], size: &Rect, quality: u8) -> anyhow::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    match encoding {
+        Encoding::Jpeg => {
+            let encoder = jpeg_encoder::Encoder::new(&mut out, quality);
+            encoder.encode(
+                data,
+                size.width.try_into()?,
+                size.height.try_into()?,
+                jpeg_encoder::ColorType::Rgba,
+            )?;
+        }
+        Encoding::Png => image::write_png(&mut out, data, size)?,
+        Encoding::Raw => {
+            out.extend_from_slice(&(size.width as u32).to_le_bytes());
+            out.extend_from_slice(&(size.height as u32).to_le_bytes());
+            out.extend_from_slice(data);
+        }
+    }
+    Ok(out)
+}
+
+/// Renders `demo` in real time at up to `fps` frames per second and
+/// publishes every frame, scaled to `size`, after `passes` and with `hud`
+/// drawn on it, to `broadcast`. Guest failures show up as error frames and
+/// restarts like in a window, so the stream only ends if the host fails.
+pub fn run(
+    demo: &mut Supervisor,
+    size: &Rect,
+    fps: f64,
+    scaler: &mut Scaler,
+    passes: &[Pass],
+    hud: &mut Hud,
+    broadcast: &Broadcast,
+) -> anyhow::Result<()> {
+    let mut pacer = Pacer::new(fps, Wait::Sleep, Duration::ZERO);
+    hud.set_target(pacer.period());
+    let playback = Playback::default();
+    loop {
+        pacer.wait();
+        if broadcast.has_subscribers() {
+            let time = playback.time();
+            let mut image = scaler.render(|size| demo.render_frame(time, size), size, passes)?;
+            hud.draw_demo(&mut image, time, demo.runner());
+            let start = Instant::now();
+            broadcast.publish(&image.data, &image.size)?;
+            hud.record(scaler.render_time(), start.elapsed());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{BufRead, Read};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[test]
+    fn test_broadcast() {
+        let broadcast = Broadcast::new(80);
+        let http = broadcast.listen_http("127.0.0.1:0").unwrap();
+        let raw = broadcast.listen_raw("127.0.0.1:0").unwrap();
+
+        let mut png = TcpStream::connect(http).unwrap();
+        write!(png, "GET /png HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut raw = TcpStream::connect(raw).unwrap();
+        let size = Rect {
+            width: 2,
+            height: 1,
+        };
+        let data = [255, 0, 0, 255, 0, 0, 255, 255];
+        // Keep publishing until both clients have subscribed and read a
+        // frame.
+        let done = Arc::new(AtomicBool::new(false));
+        let publisher = std::thread::spawn({
+            let broadcast = broadcast.clone();
+            let done = done.clone();
+            let size = size.clone();
+            move || {
+                while !done.load(Ordering::Relaxed) {
+                    broadcast.publish(&data, &size).unwrap();
+                    std::thread::sleep(Duration::from_millis(5));
+                }
+            }
+        });
+
+        let mut frame = [0; 16];
+        raw.read_exact(&mut frame).unwrap();
+        assert_eq!(
+            frame,
+            [2, 0, 0, 0, 1, 0, 0, 0, 255, 0, 0, 255, 0, 0, 255, 255]
+        );
+
+        let mut reader = BufReader::new(&png);
+        let mut headers = String::new();
+        while !headers.ends_with("Content-Type: image/png\r\n") {
+            reader.read_line(&mut headers).unwrap();
+        }
+        assert!(headers.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(headers.contains("multipart/x-mixed-replace; boundary=frame"));
+        let mut length = String::new();
+        reader.read_line(&mut length).unwrap();
+        reader.read_line(&mut String::new()).unwrap();
+        let length: usize = length["Content-Length: ".len()..].trim().parse().unwrap();
+        let mut image = vec![0; length];
+        reader.read_exact(&mut image).unwrap();
+        assert_eq!(&image[..8], b"\x89PNG\r\n\x1a\n");
+
+        done.store(true, Ordering::Relaxed);
+        publisher.join().unwrap();
+
+        let jpeg = encode(Encoding::Jpeg, &data, &size, 80).unwrap();
+        assert_eq!(jpeg[..2], [0xff, 0xd8]);
+    }
+}
@@ -0,0 +1,274 @@
+use crate::canvas::{self, Canvas};
+use crate::font;
+use crate::plugin::{DemoRunner, FrameView, Input, PixelFormat, Rect, Region, Resolution};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// How often a failing guest may be restarted.
+#[derive(Clone, Debug)]
+pub struct RestartLimit {
+    pub max_restarts: usize,
+    pub period: Duration,
+}
+
+impl Default for RestartLimit {
+    fn default() -> Self {
+        RestartLimit {
+            max_restarts: 5,
+            period: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Keeps a demo on screen when its guest traps. Failures are logged with the
+/// wasm backtrace and the module is instantiated again, no more often than
+/// `RestartLimit` allows. Until a restart succeeds, frames show the error.
+pub struct Supervisor {
+    runner: DemoRunner,
+    restarts: RestartHistory,
+    failure: Option<String>,
+    error_frame: Option<(Rect, Vec<u8>)>,
+    error_damage: Vec<Region>,
+}
+
+impl Supervisor {
+    pub fn new(runner: DemoRunner, limit: RestartLimit) -> Supervisor {
+        Supervisor {
+            runner,
+            restarts: RestartHistory {
+                limit,
+                restarts: VecDeque::new(),
+            },
+            failure: None,
+            error_frame: None,
+            error_damage: Vec::new(),
+        }
+    }
+
+    pub fn runner(&mut self) -> &mut DemoRunner {
+        &mut self.runner
+    }
+
+    /// Why the guest is not running, if it failed.
+    pub fn failure(&self) -> Option<&str> {
+        self.failure.as_deref()
+    }
+
+    /// Loads the module again, e.g. after a fix to a guest that failed.
+    pub fn reload(&mut self) -> anyhow::Result<()> {
+        self.runner.reload()?;
+        self.started("guest reloaded");
+        Ok(())
+    }
+
+    /// The resolution the guest asked for in `set_dpi`.
+    pub fn resolution(&self) -> Resolution {
+        self.runner.resolution()
+    }
+
+    /// Tells the guest the display DPI, like `DemoRunner::call_set_dpi`, and
+    /// returns the resolution it wants. Traps are handled like traps while
+    /// rendering, and a failed guest is told once it restarts.
+    pub fn set_dpi(&mut self, dpi: i32) -> Resolution {
+        if self.failure.is_some() {
+            self.runner.set_dpi_on_restart(dpi);
+        } else if let Err(error) = self.runner.call_set_dpi(dpi) {
+            self.fail(error);
+        }
+        self.runner.resolution()
+    }
+
+    /// Lets the guest pick its size, like `DemoRunner::call_set_dimensions`,
+    /// or picks `preferred` for it if it fails.
+    pub fn set_dimensions(&mut self, dpi: i32, min: &Rect, preferred: &Rect, max: &Rect) -> Rect {
+        if self.failure.is_none() {
+            match self.runner.call_set_dimensions(dpi, min, preferred, max) {
+                Ok(size) => return size,
+                Err(error) => self.fail(error),
+            }
+        }
+        preferred.clone()
+    }
+
+    /// Passes input to the guest unless it failed. Traps are handled like
+    /// traps while rendering.
+    pub fn send_input(&mut self, input: &Input) {
+        if self.failure.is_none() {
+            if let Err(error) = self.runner.send_input(input) {
+                self.fail(error);
+            }
+        }
+    }
+
+    /// Renders a frame, or an error frame if the guest fails.
+    pub fn render_frame(&mut self, time: f64, size: &Rect) -> anyhow::Result<FrameView<'_>> {
+        if self.failure.is_some() && self.restarts.try_restart(Instant::now()) {
+            match self.runner.restart() {
+                Ok(()) => self.started("guest restarted"),
+                Err(error) => self.fail(error),
+            }
+        }
+
+        if self.failure.is_none() {
+            match self.runner.render_frame(time, size).map(|_| ()) {
+                Ok(()) => return self.runner.frame(),
+                Err(error) => self.fail(error),
+            }
+        }
+
+        Ok(self.error_frame(size))
+    }
+
+    fn started(&mut self, message: &str) {
+        self.runner.log(message);
+        self.failure = None;
+    }
+
+    fn fail(&mut self, error: anyhow::Error) {
+        // The debug format includes the wasm backtrace attached to traps.
+        self.runner.log(&format!("guest failed: {error:?}"));
+        self.failure = Some(error.to_string());
+        self.error_frame = None;
+    }
+
+    fn error_frame(&mut self, size: &Rect) -> FrameView<'_> {
+        let redraw = self.error_frame.as_ref().map(|(s, _)| s) != Some(size);
+        if redraw {
+            let message = self.failure.as_deref().unwrap_or_default();
+            self.error_frame = Some((size.clone(), draw_error(size, message)));
+        }
+        self.error_damage.clear();
+        if redraw {
+            self.error_damage.push(Region::full(size));
+        }
+        let (size, data) = self.error_frame.as_ref().unwrap();
+        FrameView {
+            data,
+            size: size.clone(),
+            stride: size.width as usize * 4,
+            format: PixelFormat::Rgba8,
+            damage: &self.error_damage,
+        }
+    }
+}
+
+struct RestartHistory {
+    limit: RestartLimit,
+    restarts: VecDeque<Instant>,
+}
+
+impl RestartHistory {
+    /// Records a restart at `now` unless that would exceed the limit.
+    fn try_restart(&mut self, now: Instant) -> bool {
+        while let Some(&first) = self.restarts.front() {
+            if now.duration_since(first) < self.limit.period {
+                break;
+            }
+            self.restarts.pop_front();
+        }
+        if self.restarts.len() < self.limit.max_restarts {
+            self.restarts.push_back(now);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+fn draw_error(size: &Rect, message: &str) -> Vec<u8> {
+    const MARGIN: i32 = 16;
+    let mut data = vec![0; size.width as usize * size.height as usize * 4];
+    let mut canvas = Canvas::new(&mut data, size);
+    canvas.fill([48, 0, 0, 255]);
+    let scale = if size.width >= 640 { 2 } else { 1 };
+    let columns = (size.width - 2 * MARGIN) / (font::WIDTH as i32 * scale);
+    let mut y = MARGIN;
+    canvas.draw_text(
+        MARGIN,
+        y,
+        "The demo stopped working",
+        scale,
+        [255, 255, 255, 255],
+    );
+    y += 2 * font::HEIGHT as i32 * scale;
+    for line in canvas::wrap(message, columns.max(1) as usize) {
+        canvas.draw_text(MARGIN, y, &line, scale, [255, 160, 160, 255]);
+        y += font::HEIGHT as i32 * scale;
+    }
+    data
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_restart_limit() {
+        let mut history = RestartHistory {
+            limit: RestartLimit {
+                max_restarts: 2,
+                period: Duration::from_secs(10),
+            },
+            restarts: VecDeque::new(),
+        };
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        assert!(history.try_restart(at(0)));
+        assert!(history.try_restart(at(5)));
+        assert!(!history.try_restart(at(6)));
+        assert!(history.try_restart(at(11)));
+        assert!(!history.try_restart(at(12)));
+        assert!(history.try_restart(at(16)));
+    }
+
+    #[test]
+    fn test_set_dpi() {
+        // Wants logical resolution, but traps at 192 DPI.
+        let runner = crate::create_wat(
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "set_dpi") (param i32) (result i32)
+                    (if (i32.eq (local.get 0) (i32.const 192)) (then unreachable))
+                    i32.const 1)
+                (func (export "render") (param f64 i32 i32) (result i32) i32.const 0))"#,
+        )
+        .unwrap();
+        let mut supervisor = Supervisor::new(runner, RestartLimit::default());
+        let size = Rect {
+            width: 4,
+            height: 4,
+        };
+        assert_eq!(supervisor.set_dpi(96), Resolution::Logical);
+        assert_eq!(supervisor.set_dpi(192), Resolution::Logical);
+        assert!(supervisor.failure().is_some());
+        // Restarting at 192 DPI fails again, so frames show the error.
+        let frame = supervisor.render_frame(0.0, &size).unwrap();
+        assert_eq!(frame.data[..4], [48, 0, 0, 255]);
+        assert!(supervisor.failure().is_some());
+        // Moving back to 96 DPI lets the guest restart.
+        assert_eq!(supervisor.set_dpi(96), Resolution::Logical);
+        let frame = supervisor.render_frame(0.0, &size).unwrap();
+        assert_eq!(frame.data, [0; 64]);
+        assert_eq!(supervisor.failure(), None);
+        assert_eq!(supervisor.resolution(), Resolution::Logical);
+
+        let min = Rect {
+            width: 1,
+            height: 1,
+        };
+        let mut failing = Supervisor::new(
+            crate::create_wat(
+                r#"(module
+                    (memory (export "memory") 1)
+                    (func (export "set_dimensions")
+                        (param i32 i32 i32 i32 i32 i32 i32) (result i32 i32)
+                        unreachable)
+                    (func (export "render") (param f64 i32 i32) (result i32) i32.const 0))"#,
+            )
+            .unwrap(),
+            RestartLimit::default(),
+        );
+        assert_eq!(failing.set_dimensions(96, &min, &size, &size), size);
+        assert!(failing.failure().is_some());
+    }
+}
@@ -0,0 +1,867 @@
+use crate::catalogue;
+use crate::config;
+use crate::debugger::{self, Console};
+use crate::hud::Hud;
+use crate::pacing::Pacer;
+use crate::playback::Playback;
+use crate::plugin::{self, DemoRunner, Input};
+use crate::postprocess;
+use crate::remote::{self, Remote};
+use crate::scale::Scaler;
+use crate::screenshot;
+use crate::supervisor::{RestartLimit, Supervisor};
+use anyhow::Context;
+use std::ffi::c_void;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use windows::{
+    core::*, Foundation::Numerics::*, Win32::Foundation::*, Win32::Graphics::Direct2D::Common::*,
+    Win32::Graphics::Direct2D::*, Win32::Graphics::Direct3D::*, Win32::Graphics::Direct3D11::*,
+    Win32::Graphics::Dxgi::Common::*, Win32::Graphics::Dxgi::*, Win32::Graphics::Gdi::*,
+    Win32::System::Com::*, Win32::System::LibraryLoader::*, Win32::System::Performance::*,
+    Win32::System::SystemInformation::GetLocalTime, Win32::UI::Animation::*, Win32::UI::HiDpi::*,
+    Win32::UI::Input::KeyboardAndMouse::VK_F12, Win32::UI::Input::KeyboardAndMouse::VK_F3,
+    Win32::UI::WindowsAndMessaging::*,
+};
+
+pub struct Settings {
+    /// Size bounds, clear colour, vsync and frame pacing.
+    pub config: config::Settings,
+    /// Cover the primary screen with a borderless window.
+    pub fullscreen: bool,
+    pub playback: Playback,
+    /// Takes commands in between frames.
+    pub remote: Option<Remote>,
+    /// Takes debug console commands in between frames.
+    pub console: Option<Console>,
+    /// Drawn over frames while it is visible; F3 toggles it.
+    pub hud: Hud,
+    /// Where to save what the runner recorded when the window closes.
+    pub record: Option<PathBuf>,
+}
+
+/// Shows the demo in a window until the window is closed.
+pub fn run(demo_runner: DemoRunner, settings: Settings) -> anyhow::Result<()> {
+    unsafe {
+        CoInitializeEx(None, COINIT_MULTITHREADED)?;
+        SetProcessDpiAwarenessContext(DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2)?;
+    }
+    let mut window = Window::new(demo_runner, settings)?;
+    window.run()
+}
+
+struct Window {
+    handle: HWND,
+    factory: ID2D1Factory1,
+    dxfactory: IDXGIFactory2,
+    manager: IUIAnimationManager,
+    variable: IUIAnimationVariable,
+
+    target: Option<ID2D1DeviceContext>,
+    swapchain: Option<IDXGISwapChain1>,
+    brush: Option<ID2D1SolidColorBrush>,
+    clock: Option<ID2D1Bitmap1>,
+    /// Holds post-processed frames, which may differ in size from `clock`.
+    processed: Option<ID2D1Bitmap1>,
+    dpi: f32,
+    visible: bool,
+    occlusion: u32,
+    frequency: i64,
+
+    demo: Supervisor,
+    config: config::Settings,
+    scaler: Scaler,
+    pacer: Pacer,
+    fullscreen: bool,
+    playback: Playback,
+    remote: Option<Remote>,
+    console: Option<Console>,
+    hud: Hud,
+    record: Option<PathBuf>,
+    /// How long the guest took to render the frame being shown.
+    guest_time: Duration,
+    /// The time and size the frame being shown was rendered at.
+    rendered: Option<(f64, plugin::Rect)>,
+}
+
+impl Window {
+    fn new(demo_runner: DemoRunner, settings: Settings) -> anyhow::Result<Self> {
+        let factory = create_factory()?;
+        let dxfactory: IDXGIFactory2 = unsafe { CreateDXGIFactory1()? };
+        let manager: IUIAnimationManager =
+            unsafe { CoCreateInstance(&UIAnimationManager, None, CLSCTX_ALL)? };
+        let transition = create_transition()?;
+
+        let dpi = unsafe { GetDpiForSystem() } as f32;
+
+        let mut frequency = 0;
+        unsafe { QueryPerformanceFrequency(&mut frequency)? };
+
+        let variable = unsafe {
+            let variable = manager.CreateAnimationVariable(0.0)?;
+
+            manager.ScheduleTransition(&variable, &transition, get_time(frequency)?)?;
+
+            variable
+        };
+
+        let pacer = settings.config.pacer();
+        let mut hud = settings.hud;
+        hud.set_target(pacer.period());
+
+        Ok(Window {
+            handle: HWND(0),
+            factory,
+            dxfactory,
+            manager,
+            variable,
+            target: None,
+            swapchain: None,
+            brush: None,
+            clock: None,
+            processed: None,
+            dpi,
+            visible: false,
+            occlusion: 0,
+            frequency,
+            demo: Supervisor::new(demo_runner, RestartLimit::default()),
+            scaler: settings.config.scaler(),
+            pacer,
+            config: settings.config,
+            fullscreen: settings.fullscreen,
+            playback: settings.playback,
+            remote: settings.remote,
+            console: settings.console,
+            hud,
+            record: settings.record,
+            guest_time: Duration::ZERO,
+            rendered: None,
+        })
+    }
+
+    fn render(&mut self) -> anyhow::Result<()> {
+        if self.target.is_none() {
+            let device = create_device()?;
+            let target = create_render_target(&self.factory, &device)?;
+            unsafe { target.SetDpi(self.dpi, self.dpi) };
+
+            let swapchain = create_swapchain(&device, self.handle)?;
+            create_swapchain_bitmap(&swapchain, &target)?;
+
+            self.brush = create_brush(&target).ok();
+            self.target = Some(target);
+            self.swapchain = Some(swapchain);
+            self.create_device_size_resources()?;
+        }
+
+        let taken_target = self.target.take(); // make borrow checker happy
+        let target = taken_target.as_ref().unwrap();
+        unsafe { target.BeginDraw() };
+        // Finish drawing even if the frame failed, so the target can be used
+        // for the next one.
+        let drawn = self.draw(target);
+        let presenting = Instant::now();
+        let ended = unsafe { target.EndDraw(None, None) };
+        self.target = taken_target; // put it back
+        drawn?;
+        ended?;
+
+        if let Err(error) = self.present(self.config.vsync as u32, 0) {
+            if error.code() == DXGI_STATUS_OCCLUDED {
+                self.occlusion = unsafe {
+                    self.dxfactory
+                        .RegisterOcclusionStatusWindow(self.handle, WM_USER)?
+                };
+                self.visible = false;
+            } else {
+                self.release_device();
+            }
+        }
+        self.hud.record(self.guest_time, presenting.elapsed());
+
+        Ok(())
+    }
+
+    fn release_device(&mut self) {
+        self.target = None;
+        self.swapchain = None;
+        self.release_device_resources();
+    }
+
+    fn release_device_resources(&mut self) {
+        self.brush = None;
+        self.clock = None;
+        self.processed = None;
+    }
+
+    fn present(&self, sync: u32, flags: u32) -> Result<()> {
+        unsafe { Ok(self.swapchain.as_ref().unwrap().Present(sync, flags).ok()?) }
+    }
+
+    fn draw(&mut self, target: &ID2D1DeviceContext) -> anyhow::Result<()> {
+        if let Some(remote) = &self.remote {
+            remote.poll(|command| remote::apply(command, &mut self.playback, &mut self.demo));
+        }
+        if let Some(console) = &self.console {
+            let frame_time = self.config.target_frame_time.as_secs_f64();
+            console.poll(|line| {
+                let runner = self.demo.runner();
+                print!(
+                    "{}",
+                    debugger::run_line(line, &mut self.playback, runner, frame_time)
+                );
+            });
+        }
+        let clock = self.clock.as_ref().unwrap();
+
+        unsafe {
+            let now = get_time(self.frequency)?;
+            self.manager.Update(now, None)?;
+
+            let config::Color { r, g, b } = self.config.clear_color;
+            target.Clear(Some(&D2D1_COLOR_F { r, g, b, a: 1.0 }));
+
+            let px_size = clock.GetPixelSize();
+            let output = plugin::Rect {
+                width: px_size.width as i32,
+                height: px_size.height as i32,
+            };
+            let size = self.scaler.render_size(&output);
+            let time = self.playback.time();
+            // While paused, only render when the time or size moved, so
+            // looking around in the console doesn't change the guest's state.
+            let rendered = Some((time, size.clone()));
+            let frame = if !self.playback.is_playing() && self.rendered == rendered {
+                self.demo.runner().frame()?
+            } else {
+                self.rendered = rendered;
+                let start = Instant::now();
+                let frame = self.demo.render_frame(time, &size)?;
+                self.guest_time = start.elapsed();
+                self.scaler.record(self.guest_time);
+                frame
+            };
+            let _var_val = self.variable.GetValue()?;
+            if size != output || !self.config.postprocess.is_empty() || self.hud.is_visible() {
+                // Scaling, passes and the HUD look at the whole frame, so
+                // every frame is processed and uploaded in full.
+                let mut image = postprocess::apply(
+                    &self.config.postprocess,
+                    self.scaler.upscale(&frame, &output),
+                )?;
+                self.hud
+                    .draw_demo(&mut image, self.playback.time(), self.demo.runner());
+                let stale = self.processed.as_ref().map_or(true, |bitmap| {
+                    let size = bitmap.GetPixelSize();
+                    (size.width as i32, size.height as i32) != (image.size.width, image.size.height)
+                });
+                if stale {
+                    self.processed =
+                        Some(create_bitmap(target, &image.size, plugin::BASE_DPI as f32)?);
+                }
+                let processed = self.processed.as_ref().unwrap();
+                processed.CopyFromMemory(
+                    None,
+                    image.data.as_ptr() as *const c_void,
+                    image.size.width as u32 * 4,
+                )?;
+                let size = target.GetSize();
+                target.DrawBitmap(
+                    processed,
+                    Some(&D2D_RECT_F {
+                        left: 0.0,
+                        top: 0.0,
+                        right: size.width,
+                        bottom: size.height,
+                    }),
+                    1.0,
+                    D2D1_BITMAP_INTERPOLATION_MODE_LINEAR,
+                    None,
+                );
+                return Ok(());
+            }
+
+            for region in frame.damage {
+                let rect = D2D_RECT_U {
+                    left: region.x as u32,
+                    top: region.y as u32,
+                    right: (region.x + region.width) as u32,
+                    bottom: (region.y + region.height) as u32,
+                };
+                // Damage is always clipped to the frame.
+                let Some(data) = frame.region_data(region) else {
+                    continue;
+                };
+                let data_ptr: *const u8 = data.as_ptr();
+                clock.CopyFromMemory(
+                    Some(&rect),
+                    data_ptr as *const c_void,
+                    frame.stride as u32,
+                )?;
+            }
+            target.DrawBitmap(
+                clock,
+                None,
+                1.0,
+                D2D1_BITMAP_INTERPOLATION_MODE_LINEAR,
+                None,
+            );
+        }
+
+        Ok(())
+    }
+
+    fn create_device_size_resources(&mut self) -> Result<()> {
+        let target = self.target.as_ref().unwrap();
+        let clock = self.create_clock(target)?;
+        self.clock = Some(clock);
+        self.demo.runner().invalidate();
+
+        Ok(())
+    }
+
+    fn create_clock(&self, target: &ID2D1DeviceContext) -> Result<ID2D1Bitmap1> {
+        let size_f = unsafe { target.GetSize() };
+        let logical = plugin::Rect {
+            width: size_f.width as i32,
+            height: size_f.height as i32,
+        };
+        let size = self
+            .demo
+            .resolution()
+            .render_size(&logical, self.dpi as i32);
+
+        // The bitmap always covers the whole target, so at logical resolution
+        // it is drawn stretched rather than at its own DPI.
+        let bitmap_dpi = match self.demo.resolution() {
+            plugin::Resolution::Physical => self.dpi,
+            plugin::Resolution::Logical => plugin::BASE_DPI as f32,
+        };
+        create_bitmap(target, &size, bitmap_dpi)
+    }
+
+    fn resize_swapchain_bitmap(&mut self) -> anyhow::Result<()> {
+        if let Some(target) = &self.target {
+            let swapchain = self.swapchain.as_ref().unwrap();
+            unsafe { target.SetTarget(None) };
+
+            if unsafe {
+                swapchain
+                    .ResizeBuffers(0, 0, 0, DXGI_FORMAT_UNKNOWN, 0)
+                    .is_ok()
+            } {
+                create_swapchain_bitmap(swapchain, target)?;
+                self.create_device_size_resources()?;
+            } else {
+                self.release_device();
+            }
+
+            self.render()?;
+        }
+
+        Ok(())
+    }
+
+    fn set_dpi(&mut self, dpi: f32, suggested: &RECT) -> anyhow::Result<()> {
+        self.dpi = dpi;
+        self.demo.set_dpi(dpi as i32);
+        if let Some(target) = &self.target {
+            unsafe { target.SetDpi(dpi, dpi) };
+            self.create_device_size_resources()?;
+        }
+        unsafe {
+            SetWindowPos(
+                self.handle,
+                None,
+                suggested.left,
+                suggested.top,
+                suggested.right - suggested.left,
+                suggested.bottom - suggested.top,
+                SWP_NOZORDER | SWP_NOACTIVATE,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Saves the frame being shown, re-rendered at `screenshot_scale` times
+    /// the window's size, in the screenshot directory.
+    fn screenshot(&mut self) -> anyhow::Result<PathBuf> {
+        let clock = self.clock.as_ref().context("nothing has been drawn yet")?;
+        let px_size = unsafe { clock.GetPixelSize() };
+        let scaled =
+            |length: u32| ((length as f64 * self.config.screenshot_scale).round() as i32).max(1);
+        let size = plugin::Rect {
+            width: scaled(px_size.width),
+            height: scaled(px_size.height),
+        };
+        let max = self
+            .demo
+            .resolution()
+            .render_size(&self.config.max_size, self.dpi as i32);
+        let time = self.playback.time();
+        let runner = self.demo.runner();
+        let name = runner
+            .path()
+            .map_or_else(|| "demo".to_string(), catalogue::module_name);
+        // Named after the demo time, so saving a paused frame twice
+        // overwrites the same file with the same image.
+        let path = self
+            .config
+            .screenshot_dir
+            .join(format!("{name}-{time:.3}.png"));
+        screenshot::save(
+            runner,
+            time,
+            &size,
+            &max,
+            &self.config.postprocess,
+            &self.hud,
+            &path,
+        )?;
+        Ok(path)
+    }
+
+    /// Converts the client coordinates of a mouse message to frame pixels,
+    /// at the render scale the frame was drawn at.
+    fn frame_position(&self, lparam: LPARAM) -> (i32, i32) {
+        let x = (lparam.0 & 0xffff) as i16 as i32;
+        let y = ((lparam.0 >> 16) & 0xffff) as i16 as i32;
+        let (x, y) = match &self.clock {
+            Some(clock) => {
+                let px_size = unsafe { clock.GetPixelSize() };
+                let output = plugin::Rect {
+                    width: px_size.width as i32,
+                    height: px_size.height as i32,
+                };
+                self.scaler.render_position(&output, (x, y))
+            }
+            None => (x, y),
+        };
+        match self.demo.resolution() {
+            plugin::Resolution::Physical => (x, y),
+            plugin::Resolution::Logical => {
+                let scale = plugin::scale_factor(self.dpi as i32);
+                (
+                    (x as f64 / scale).round() as i32,
+                    (y as f64 / scale).round() as i32,
+                )
+            }
+        }
+    }
+
+    /// Reports an error in handling a window message. Panicking would unwind
+    /// into the system's window procedure, and the next frame may do better.
+    fn log_error(&mut self, what: &str, result: anyhow::Result<()>) {
+        if let Err(error) = result {
+            self.demo.runner().log(&format!("{what} failed: {error:?}"));
+        }
+    }
+
+    fn message_handler(&mut self, message: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+        unsafe {
+            match message {
+                WM_PAINT => {
+                    let mut ps = PAINTSTRUCT::default();
+                    BeginPaint(self.handle, &mut ps);
+                    let result = self.render();
+                    self.log_error("rendering", result);
+                    EndPaint(self.handle, &ps);
+                    LRESULT(0)
+                }
+                WM_SIZE => {
+                    if wparam.0 != SIZE_MINIMIZED as usize {
+                        let result = self.resize_swapchain_bitmap();
+                        self.log_error("resizing", result);
+                    }
+                    LRESULT(0)
+                }
+                WM_DPICHANGED => {
+                    let result =
+                        self.set_dpi((wparam.0 & 0xffff) as f32, &*(lparam.0 as *const RECT));
+                    self.log_error("changing the DPI", result);
+                    LRESULT(0)
+                }
+                WM_DISPLAYCHANGE => {
+                    let result = self.render();
+                    self.log_error("rendering", result);
+                    LRESULT(0)
+                }
+                WM_USER => {
+                    if self.present(0, DXGI_PRESENT_TEST).is_ok() {
+                        self.dxfactory.UnregisterOcclusionStatus(self.occlusion);
+                        self.occlusion = 0;
+                        self.visible = true;
+                    }
+                    LRESULT(0)
+                }
+                WM_ACTIVATE => {
+                    self.visible = true; // TODO: unpack !HIWORD(wparam);
+                    LRESULT(0)
+                }
+                WM_MOUSEMOVE => {
+                    let (x, y) = self.frame_position(lparam);
+                    self.demo.send_input(&Input::PointerMove { x, y });
+                    LRESULT(0)
+                }
+                WM_LBUTTONDOWN | WM_LBUTTONUP | WM_RBUTTONDOWN | WM_RBUTTONUP | WM_MBUTTONDOWN
+                | WM_MBUTTONUP => {
+                    let button = match message {
+                        WM_LBUTTONDOWN | WM_LBUTTONUP => 0,
+                        WM_RBUTTONDOWN | WM_RBUTTONUP => 1,
+                        _ => 2,
+                    };
+                    let pressed =
+                        matches!(message, WM_LBUTTONDOWN | WM_RBUTTONDOWN | WM_MBUTTONDOWN);
+                    self.demo
+                        .send_input(&Input::PointerButton { button, pressed });
+                    LRESULT(0)
+                }
+                WM_KEYDOWN if wparam.0 == VK_F3.0 as usize => {
+                    self.hud.toggle();
+                    // The frame is uploaded in full while the HUD is shown,
+                    // so the damaged regions alone won't do afterwards.
+                    self.demo.runner().invalidate();
+                    LRESULT(0)
+                }
+                WM_KEYDOWN if wparam.0 == VK_F12.0 as usize => {
+                    let message = match self.screenshot() {
+                        Ok(path) => format!("saved {}", path.display()),
+                        Err(error) => format!("screenshot failed: {error:#}"),
+                    };
+                    self.demo.runner().log(&message);
+                    LRESULT(0)
+                }
+                WM_KEYDOWN | WM_KEYUP => {
+                    self.demo.send_input(&Input::Key {
+                        code: wparam.0 as i32,
+                        pressed: message == WM_KEYDOWN,
+                    });
+                    LRESULT(0)
+                }
+                WM_DESTROY => {
+                    PostQuitMessage(0);
+                    LRESULT(0)
+                }
+                _ => DefWindowProcA(self.handle, message, wparam, lparam),
+            }
+        }
+    }
+
+    fn run(&mut self) -> anyhow::Result<()> {
+        unsafe {
+            let instance = GetModuleHandleA(None)?;
+            debug_assert!(instance.0 != 0);
+            let window_class = s!("window");
+
+            let wc = WNDCLASSA {
+                hCursor: LoadCursorW(None, IDC_HAND)?,
+                hInstance: instance.into(),
+                lpszClassName: window_class,
+
+                style: CS_HREDRAW | CS_VREDRAW,
+                lpfnWndProc: Some(Self::wndproc),
+                ..Default::default()
+            };
+
+            let atom = RegisterClassA(&wc);
+            debug_assert!(atom != 0);
+
+            let dpi = self.dpi as i32;
+            self.demo.set_dpi(dpi);
+            let logical = self.demo.set_dimensions(
+                dpi,
+                &self.config.min_size,
+                &self.config.size,
+                &self.config.max_size,
+            );
+            println!("demo selected {}, {}", logical.width, logical.height);
+            let plugin::Rect { width, height } =
+                plugin::Resolution::Physical.render_size(&logical, dpi);
+            let mut r = RECT {
+                left: 0,
+                right: width,
+                top: 0,
+                bottom: height,
+            };
+            let (style, x, y) = if self.fullscreen {
+                r.right = GetSystemMetrics(SM_CXSCREEN);
+                r.bottom = GetSystemMetrics(SM_CYSCREEN);
+                (WS_POPUP | WS_VISIBLE, 0, 0)
+            } else {
+                AdjustWindowRectExForDpi(
+                    &mut r as *mut RECT,
+                    WS_OVERLAPPEDWINDOW,
+                    false,
+                    WINDOW_EX_STYLE::default(),
+                    dpi as u32,
+                )?;
+                (
+                    WS_OVERLAPPEDWINDOW | WS_VISIBLE,
+                    CW_USEDEFAULT,
+                    CW_USEDEFAULT,
+                )
+            };
+
+            let handle = CreateWindowExA(
+                WINDOW_EX_STYLE::default(),
+                window_class,
+                s!("Sample Window"),
+                style,
+                x,
+                y,
+                r.right - r.left,
+                r.bottom - r.top,
+                None,
+                None,
+                instance,
+                Some(self as *mut _ as _),
+            );
+
+            debug_assert!(handle.0 != 0);
+            debug_assert!(handle == self.handle);
+            let mut message = MSG::default();
+
+            'frames: loop {
+                if self.visible {
+                    self.pacer.wait();
+                    let result = self.render();
+                    self.log_error("rendering", result);
+                    self.playback.advance(self.pacer.frame_time().as_secs_f64());
+
+                    while PeekMessageA(&mut message, None, 0, 0, PM_REMOVE).into() {
+                        if message.message == WM_QUIT {
+                            break 'frames;
+                        }
+                        DispatchMessageA(&message);
+                    }
+                } else {
+                    GetMessageA(&mut message, None, 0, 0);
+
+                    if message.message == WM_QUIT {
+                        break 'frames;
+                    }
+
+                    DispatchMessageA(&message);
+                }
+            }
+        }
+        println!("{}", self.pacer.stats());
+        self.save_recording()
+    }
+
+    /// Saves what the runner recorded, if it hasn't been saved yet.
+    fn save_recording(&mut self) -> anyhow::Result<()> {
+        if let Some(path) = &self.record {
+            if let Some(replay) = self.demo.runner().take_recording() {
+                replay.save(path)?;
+                println!("recorded {} frames to {}", replay.frames(), path.display());
+            }
+        }
+        Ok(())
+    }
+
+    extern "system" fn wndproc(
+        window: HWND,
+        message: u32,
+        wparam: WPARAM,
+        lparam: LPARAM,
+    ) -> LRESULT {
+        unsafe {
+            if message == WM_NCCREATE {
+                let cs = lparam.0 as *const CREATESTRUCTA;
+                let this = (*cs).lpCreateParams as *mut Self;
+                (*this).handle = window;
+
+                SetWindowLongPtrA(window, GWLP_USERDATA, this as _);
+            } else {
+                let this = GetWindowLongPtrA(window, GWLP_USERDATA) as *mut Self;
+
+                if !this.is_null() {
+                    return (*this).message_handler(message, wparam, lparam);
+                }
+            }
+
+            DefWindowProcA(window, message, wparam, lparam)
+        }
+    }
+}
+
+impl Drop for Window {
+    /// A recording is most useful when the window failed, so it is saved
+    /// however `run` ended.
+    fn drop(&mut self) {
+        if let Err(error) = self.save_recording() {
+            eprintln!("saving the recording failed: {error:#}");
+        }
+    }
+}
+
+fn get_time(frequency: i64) -> Result<f64> {
+    unsafe {
+        let mut time = 0;
+        QueryPerformanceCounter(&mut time)?;
+        Ok(time as f64 / frequency as f64)
+    }
+}
+
+fn create_bitmap(
+    target: &ID2D1DeviceContext,
+    size: &plugin::Rect,
+    dpi: f32,
+) -> Result<ID2D1Bitmap1> {
+    let size_u = D2D_SIZE_U {
+        width: size.width as u32,
+        height: size.height as u32,
+    };
+    let properties = D2D1_BITMAP_PROPERTIES1 {
+        pixelFormat: D2D1_PIXEL_FORMAT {
+            format: DXGI_FORMAT_B8G8R8A8_UNORM,
+            alphaMode: D2D1_ALPHA_MODE_PREMULTIPLIED,
+        },
+        dpiX: dpi,
+        dpiY: dpi,
+        bitmapOptions: D2D1_BITMAP_OPTIONS_TARGET,
+        ..Default::default()
+    };
+
+    unsafe { target.CreateBitmap2(size_u, None, 0, &properties) }
+}
+
+fn create_brush(target: &ID2D1DeviceContext) -> anyhow::Result<ID2D1SolidColorBrush> {
+    let color = D2D1_COLOR_F {
+        r: 0.92,
+        g: 0.38,
+        b: 0.208,
+        a: 1.0,
+    };
+
+    let properties = D2D1_BRUSH_PROPERTIES {
+        opacity: 0.8,
+        transform: Matrix3x2::identity(),
+    };
+
+    unsafe { Ok(target.CreateSolidColorBrush(&color, Some(&properties))?) }
+}
+
+fn create_factory() -> anyhow::Result<ID2D1Factory1> {
+    let mut options = D2D1_FACTORY_OPTIONS::default();
+
+    if cfg!(debug_assertions) {
+        options.debugLevel = D2D1_DEBUG_LEVEL_INFORMATION;
+    }
+
+    unsafe {
+        Ok(D2D1CreateFactory(
+            D2D1_FACTORY_TYPE_SINGLE_THREADED,
+            Some(&options),
+        )?)
+    }
+}
+
+fn create_transition() -> anyhow::Result<IUIAnimationTransition> {
+    unsafe {
+        let library: IUIAnimationTransitionLibrary =
+            CoCreateInstance(&UIAnimationTransitionLibrary, None, CLSCTX_ALL)?;
+        Ok(library.CreateAccelerateDecelerateTransition(5.0, 1.0, 0.2, 0.8)?)
+    }
+}
+
+fn create_device_with_type(drive_type: D3D_DRIVER_TYPE) -> Result<ID3D11Device> {
+    let mut flags = D3D11_CREATE_DEVICE_BGRA_SUPPORT;
+
+    // if cfg!(debug_assertions) {
+    //     flags |= D3D11_CREATE_DEVICE_DEBUG;
+    // }
+
+    let mut device = None;
+
+    unsafe {
+        D3D11CreateDevice(
+            None,
+            drive_type,
+            None,
+            flags,
+            None,
+            D3D11_SDK_VERSION,
+            Some(&mut device),
+            None,
+            None,
+        )
+        .map(|()| device.unwrap())
+    }
+}
+
+fn create_device() -> Result<ID3D11Device> {
+    let mut result = create_device_with_type(D3D_DRIVER_TYPE_HARDWARE);
+
+    if let Err(err) = &result {
+        match err.code() {
+            DXGI_ERROR_UNSUPPORTED | DXGI_ERROR_SDK_COMPONENT_MISSING => {
+                result = create_device_with_type(D3D_DRIVER_TYPE_WARP);
+            }
+            _ => {}
+        }
+    }
+
+    result
+}
+
+fn create_render_target(
+    factory: &ID2D1Factory1,
+    device: &ID3D11Device,
+) -> anyhow::Result<ID2D1DeviceContext> {
+    unsafe {
+        let d2device = factory.CreateDevice(&device.cast::<IDXGIDevice>()?)?;
+
+        let target = d2device.CreateDeviceContext(D2D1_DEVICE_CONTEXT_OPTIONS_NONE)?;
+
+        target.SetUnitMode(D2D1_UNIT_MODE_DIPS);
+
+        Ok(target)
+    }
+}
+
+fn get_dxgi_factory(device: &ID3D11Device) -> Result<IDXGIFactory2> {
+    let dxdevice = device.cast::<IDXGIDevice>()?;
+    unsafe { Ok(dxdevice.GetAdapter()?.GetParent()?) }
+}
+
+fn create_swapchain_bitmap(swapchain: &IDXGISwapChain1, target: &ID2D1DeviceContext) -> Result<()> {
+    let surface: IDXGISurface = unsafe { swapchain.GetBuffer(0)? };
+
+    let props = D2D1_BITMAP_PROPERTIES1 {
+        pixelFormat: D2D1_PIXEL_FORMAT {
+            format: DXGI_FORMAT_B8G8R8A8_UNORM,
+            alphaMode: D2D1_ALPHA_MODE_IGNORE,
+        },
+        dpiX: 96.0,
+        dpiY: 96.0,
+        bitmapOptions: D2D1_BITMAP_OPTIONS_TARGET | D2D1_BITMAP_OPTIONS_CANNOT_DRAW,
+        ..Default::default()
+    };
+
+    unsafe {
+        let bitmap = target.CreateBitmapFromDxgiSurface(&surface, Some(&props))?;
+        target.SetTarget(&bitmap);
+    };
+
+    Ok(())
+}
+
+fn create_swapchain(device: &ID3D11Device, window: HWND) -> Result<IDXGISwapChain1> {
+    let factory = get_dxgi_factory(device)?;
+
+    let props = DXGI_SWAP_CHAIN_DESC1 {
+        Format: DXGI_FORMAT_B8G8R8A8_UNORM,
+        SampleDesc: DXGI_SAMPLE_DESC {
+            Count: 1,
+            Quality: 0,
+        },
+        BufferUsage: DXGI_USAGE_RENDER_TARGET_OUTPUT,
+        BufferCount: 2,
+        SwapEffect: DXGI_SWAP_EFFECT_FLIP_SEQUENTIAL,
+        ..Default::default()
+    };
+
+    unsafe { factory.CreateSwapChainForHwnd(device, window, &props, None, None) }
+}
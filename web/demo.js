@@ -46,6 +46,23 @@ class FrameRateCounter extends HTMLElement {
 }
 
 
+// Same rules as `Resolution::render_size` in the native host.
+const BASE_DPI = 96;
+const RESOLUTION_PHYSICAL = 0;
+const RESOLUTION_LOGICAL = 1;
+
+// The size bounds the native host offers `set_dimensions` by default.
+const MIN_SIZE = {width: 100, height: 100};
+const MAX_SIZE = {width: 2560, height: 1440};
+
+// Native buttons are 0 primary, 1 secondary and 2 middle; DOM ones are 0
+// primary, 1 middle and 2 secondary.
+const BUTTONS = [0, 2, 1];
+
+function currentDpi() {
+  return Math.round(window.devicePixelRatio * BASE_DPI);
+}
+
 class Demo extends HTMLElement {
   constructor() {
     super();
@@ -68,15 +85,15 @@ class Demo extends HTMLElement {
     this._frameCallback = f;
   }
 
-  #getImageData(width, height) {
-    if (this._imageData) {
-      if (width > 0 && height > 0 && (width != this._imageData.width || height != this._imageData.height)) {
-        console.log(`new imageData ${width} ${height}`);
-        this._imageData = this._ctx.getImageData(0, 0, width, height)
-      }
-    } else {
-      console.log(`new imageData ${width} ${height}`);
-      this._imageData = this._ctx.getImageData(0, 0, width, height)
+  #setSize(width, height) {
+    if (width > 0 && height > 0 && (width != this._width || height != this._height)) {
+      console.log(`new size ${width} ${height}`);
+      this._width = width;
+      this._height = height;
+      this._canvas.width = width;
+      this._canvas.height = height;
+      this._canvas.style.aspectRatio = `${width}/${height}`;
+      this._invalidated = true;
     }
   }
 
@@ -86,35 +103,212 @@ class Demo extends HTMLElement {
     return {allowed_width:dimensions[0], allowed_height:dimensions[1]};
   }
 
+  // The logical size the `width` and `height` attributes ask for.
+  #preferredSize() {
+    return {
+      width: parseInt(this.getAttribute("width")) || 640,
+      height: parseInt(this.getAttribute("height")) || 480,
+    };
+  }
+
+  // Lets the guest pick its logical size like the native host does. Guests
+  // written for this player alone may export `get_dimensions` instead.
+  #pickSize(dpi, preferred) {
+    const exports = this._wasm.exports;
+    if (exports.set_dimensions) {
+      const [width, height] = exports.set_dimensions(dpi,
+        MIN_SIZE.width, MIN_SIZE.height,
+        preferred.width, preferred.height,
+        MAX_SIZE.width, MAX_SIZE.height);
+      return {width, height};
+    }
+    if (exports.get_dimensions) {
+      const { allowed_width, allowed_height } = this.#getDimensions(dpi);
+      return {width: allowed_width, height: allowed_height};
+    }
+    return preferred;
+  }
+
+  #paramNames() {
+    const exports = this._wasm.exports;
+    const names = [];
+    if (!exports.param_name || !exports.set_param) {
+      return names;
+    }
+    for (var i = 0; ; i++) {
+      const ptr = exports.param_name(i);
+      if (ptr == 0) {
+        return names;
+      }
+      names.push(this.#readString(ptr));
+    }
+  }
+
+  // The `params` attribute holds a JSON object of parameter values by name,
+  // like `--param` on the command line.
+  #setParams() {
+    const params = JSON.parse(this.getAttribute("params") || "{}");
+    const names = this.#paramNames();
+    for (const [name, value] of Object.entries(params)) {
+      const index = names.indexOf(name);
+      if (index < 0) {
+        console.log(`the demo has no parameter "${name}"`);
+      } else {
+        this._wasm.exports.set_param(index, value);
+      }
+    }
+  }
+
+  #readString(ptr) {
+    const bytes = new Uint8Array(this._wasm.exports.memory.buffer, ptr);
+    const end = bytes.indexOf(0);
+    return new TextDecoder().decode(bytes.subarray(0, end < 0 ? 1024 : end));
+  }
+
+  // `data:` URLs are decoded here, since pages opened from `file://` may not
+  // fetch anything.
+  async #load(path) {
+    const data = path.match(/^data:[^,]*;base64,(.*)$/);
+    if (data) {
+      return Uint8Array.from(atob(data[1]), (c) => c.charCodeAt(0));
+    }
+    const response = await fetch(path);
+    return await response.arrayBuffer();
+  }
+
+  // Passes pointer and key input on as the native host does, with pointer
+  // positions in frame pixels.
+  #listen() {
+    const exports = this._wasm.exports;
+    const position = (event) => [
+      Math.floor(event.offsetX * this._width / this._canvas.clientWidth),
+      Math.floor(event.offsetY * this._height / this._canvas.clientHeight),
+    ];
+    this._canvas.tabIndex = 0;
+    this._canvas.addEventListener('pointermove', (event) => {
+      if (exports.pointer_move) {
+        exports.pointer_move(...position(event));
+      }
+    });
+    for (const type of ['pointerdown', 'pointerup']) {
+      this._canvas.addEventListener(type, (event) => {
+        if (exports.pointer_button && event.button < BUTTONS.length) {
+          exports.pointer_button(BUTTONS[event.button], type == 'pointerdown' ? 1 : 0);
+        }
+      });
+    }
+    for (const type of ['keydown', 'keyup']) {
+      this._canvas.addEventListener(type, (event) => {
+        if (exports.key) {
+          exports.key(event.keyCode, type == 'keydown' ? 1 : 0);
+        }
+      });
+    }
+  }
+
+  #renderSize(width, height) {
+    if (this._resolution == RESOLUTION_LOGICAL) {
+      return {width, height};
+    }
+    const scale = this._dpi / BASE_DPI;
+    return {
+      width: Math.max(1, Math.round(width * scale)),
+      height: Math.max(1, Math.round(height * scale)),
+    };
+  }
+
+  #setDpi(dpi) {
+    this._dpi = dpi;
+    this._resolution = this._wasm.exports.set_dpi ? this._wasm.exports.set_dpi(dpi) : RESOLUTION_PHYSICAL;
+    const { width, height } = this.#renderSize(this._logicalWidth, this._logicalHeight);
+    this.#setSize(width, height);
+  }
+
+  // A resolution media query only fires once, when the ratio moves away from
+  // the value it was built with, so it is rebuilt after every change.
+  #watchDpi() {
+    const media = matchMedia(`(resolution: ${window.devicePixelRatio}dppx)`);
+    media.addEventListener('change', () => {
+      this.#setDpi(currentDpi());
+      this.#watchDpi();
+    }, {once: true});
+  }
+
+  #resizeFramebuffer(width, height) {
+    if (!this._wasm.exports.resize_framebuffer) {
+      return;
+    }
+    if (width != this._framebufferWidth || height != this._framebufferHeight) {
+      this._wasm.exports.resize_framebuffer(width, height);
+      this._invalidated = true;
+      this._framebufferWidth = width;
+      this._framebufferHeight = height;
+    }
+  }
+
+  // Same contract as the native host: `damage` returns a pointer to an i32
+  // count followed by `x, y, width, height` tuples, and a negative count or
+  // a missing export means the whole frame changed.
+  #damage(width, height) {
+    const full = [{x: 0, y: 0, width, height}];
+    if (this._invalidated || !this._wasm.exports.damage) {
+      this._invalidated = false;
+      return full;
+    }
+    const ptr = this._wasm.exports.damage();
+    const view = new DataView(this._wasm.exports.memory.buffer);
+    const count = view.getInt32(ptr, true);
+    if (count < 0) {
+      return full;
+    }
+    const regions = [];
+    for (var i = 0; i < count; i++) {
+      const offset = ptr + 4 + i * 16;
+      const x = view.getInt32(offset, true);
+      const y = view.getInt32(offset + 4, true);
+      const left = Math.min(Math.max(x, 0), width);
+      const top = Math.min(Math.max(y, 0), height);
+      const right = Math.min(Math.max(x + view.getInt32(offset + 8, true), 0), width);
+      const bottom = Math.min(Math.max(y + view.getInt32(offset + 12, true), 0), height);
+      if (right > left && bottom > top) {
+        regions.push({x: left, y: top, width: right - left, height: bottom - top});
+      }
+    }
+    return regions;
+  }
+
   async start() {
     if (this.hasAttribute("src") && this.getAttribute("src")) {
       var path = this.getAttribute("src");
       const importObject = {
-        env: {output: (ptr) => {
-          const str = new Uint8ClampedArray(this._wasm.exports.memory.buffer, ptr, 1024);
-          var s = "";
-          for (var i = 0; i < str.length; i++) {
-            if (str[i] == 0) {
-              break;
-            }
-            s += String.fromCharCode(str[i]);
-          }
-          console.log(s)
-        }
-      },
+        env: {
+          output: (ptr) => console.log(this.#readString(ptr)),
+          // Profiling spans are only collected by the native host.
+          span_begin: (ptr) => {},
+          span_end: () => {},
+        },
       };
-        
-      const response = await fetch(path);
-      const wasmBuffer = await response.arrayBuffer();
+
+      const wasmBuffer = await this.#load(path);
       const wasmObj = await WebAssembly.instantiate(wasmBuffer, importObject);
       this._wasm = wasmObj.instance;
 
-      const dimension = this.#getDimensions(32);
-      const { allowed_width, allowed_height } = dimension;
+      // Like the native host, tell the guest the DPI before it picks a size,
+      // going by the size the page asks for until then.
+      const dpi = currentDpi();
+      const preferred = this.#preferredSize();
+      this._logicalWidth = preferred.width;
+      this._logicalHeight = preferred.height;
+      this.#setDpi(dpi);
+      const { width, height } = this.#pickSize(dpi, preferred);
 
-      this.#getImageData(allowed_width, allowed_height);
-      this._canvas.width = allowed_width;
-      this._canvas.height = allowed_height;
+      this._logicalWidth = width;
+      this._logicalHeight = height;
+      const size = this.#renderSize(width, height);
+      this.#setSize(size.width, size.height);
+      this.#watchDpi();
+      this.#setParams();
+      this.#listen();
     }
     if (this._wasm) {
       this._running = true;
@@ -128,16 +322,22 @@ class Demo extends HTMLElement {
 
   animate(timestamp) {
     if (this._ctx) {
-      this.#getImageData();
-      const data = this._imageData.data;
-
-      console.log(`rendering(${this._imageData.width}, ${this._imageData.height})`);
-      const pointer = this._wasm.exports.render(timestamp, this._imageData.width, this._imageData.height);
-      const rendered = new Uint8Array(this._wasm.exports.memory.buffer, pointer, data.length);
-      data.set(rendered);
-      this._ctx.putImageData(this._imageData, 0, 0);
+      const width = this._width;
+      const height = this._height;
+      this.#resizeFramebuffer(width, height);
+      const pointer = this._wasm.exports.render(timestamp, width, height);
+      // Wrap the guest's framebuffer directly instead of copying it out. The
+      // view has to be rebuilt every frame since growing the memory detaches
+      // the old buffer, and double-buffered guests alternate the pointer.
+      const pixels = new Uint8ClampedArray(this._wasm.exports.memory.buffer, pointer, width * height * 4);
+      const frame = new ImageData(pixels, width, height);
+      for (const region of this.#damage(width, height)) {
+        this._ctx.putImageData(frame, 0, 0, region.x, region.y, region.width, region.height);
+      }
+    }
+    if (this._frameCallback) {
+      this._frameCallback(timestamp);
     }
-    this._frameCallback(timestamp);
     if (this._running) {
       requestAnimationFrame((ts) => {
         this.animate(ts);